@@ -1,62 +1,121 @@
-use std::{fmt, process::exit, sync::{Arc, Mutex}, thread::{self, sleep}, time::Duration, u64};
+use std::{fmt, path::PathBuf, process::exit, sync::mpsc::{self, Receiver, RecvTimeoutError}, thread::{self, sleep}, time::Duration, u64};
 
 use aera::{commands::Command, properties::Properties, protobuf::{tcp_message, variable_description, DataMessage, ProtoVariable, VariableDescription}, AeraConn};
 use nalgebra::{Vector2, Vector4};
 use opencv::imgcodecs::{self, IMREAD_COLOR};
-use pixy2::PixyCamera;
-use robot::{feedback_data::{self, FeedbackData}, RobotConn, RobotFeedbackConn};
+use pixy2::{camera_settings::CameraSettings, capture::{CaptureConfig, FrameCapture, Overflow}, frame_source::{FrameSource, ImageDirectorySource, SyntheticConfig, SyntheticSource, VideoSource}, raw_recording::{RawReplaySource, RAW_RECORDING_EXTENSION}, PixyCamera};
+use robot::{alarm::{AlarmConfig, AlarmEvent, AlarmSupervisor}, collision::{Anomaly, CollisionConfig, CollisionMonitor, GraspLoadConfig}, dobot::DobotRobot, end_effector::{EndEffector, EndEffectorState, SuctionCup, SuctionCupConfig}, feedback_layout::{FeedbackLayout, LayoutSelection}, io::{IoEvent, IoMap, IoWatcher}, motion_queue::{MotionParams, MotionQueue, MotionQueueConfig}, recorder::{FeedbackRecorder, RecordFormat, RecordedField}, safety::{SafetyConfig, SafetyEnvelope}, sim::SimulatedRobot, teach::{PlaybackMode, TeachConfig, TeachSession, Trajectory}, watchdog::{Watchdog, WatchdogConfig}, Robot};
 use vision::{RecognizedArea, VisionSystem};
 
+const TRAJECTORY_DIR: &str = "trajectories";
+// Frames grabbed with the lamps on before white balance and exposure are locked
+const LIGHTING_SETTLE_FRAMES: usize = 30;
+const FRAME_TIMEOUT: Duration = Duration::from_secs(5);
+// Feedback arrives every 8 ms, so the teach session is drained often to keep its channel short
+const TEACH_POLL_INTERVAL: Duration = Duration::from_millis(100);
 
 fn main() -> anyhow::Result<()> {
     setup_logging();
 
-    log::info!("Connecting to robot");
-    let mut robot = RobotConn::connect().expect("Failed to connect to robot");
+    let safety = SafetyEnvelope::new(SafetyConfig::new())?;
+    let mut robot: Box<dyn Robot> = if std::env::args().any(|a| a == "--simulated-robot") {
+        log::info!("Using simulated robot");
+        let mut robot = SimulatedRobot::new(Vector4::new(240.0, 0.0, 0.0, 45.0));
+        robot.set_safety_envelope(Some(safety));
+        Box::new(robot)
+    } else {
+        log::info!("Connecting to robot");
+        // Picked from the message size, firmware with the legacy layout sends the same size and has to be named
+        let layouts = match arg_value("--feedback-layout") {
+            Some(name) => LayoutSelection::Fixed(FeedbackLayout::by_name(&name).unwrap_or_else(|| panic!("Unknown feedback layout {name}"))),
+            None => LayoutSelection::Auto,
+        };
+        let mut robot = DobotRobot::connect_with_layout(layouts).expect("Failed to connect to robot");
+        robot.set_safety_envelope(Some(safety));
+        Box::new(robot)
+    };
+    let io = match arg_value("--io-map") {
+        Some(path) => IoMap::load(&PathBuf::from(path))?,
+        None => IoMap::new().with_output("suction", 3),
+    };
+    let mut suction_config = SuctionCupConfig::new();
+    suction_config.vacuum_output = io.output("suction")?;
+    // No vacuum switch on the cup, tell from the joint torques whether the cube came along
+    suction_config.verification.load = Some(GraspLoadConfig::new());
+    let mut end_effector: Box<dyn EndEffector> = Box::new(SuctionCup::new(suction_config));
+
+    // Stops the robot and releases the vacuum if the module panics or loses AERA
+    let mut watchdog_config = WatchdogConfig::new();
+    watchdog_config.safe_outputs = end_effector.safe_outputs();
+    let watchdog = Watchdog::spawn(watchdog_config, robot.emergency_handle()?);
+    watchdog.install_panic_hook();
+
+    // Dropping the handle on an early return also finishes the file
+    let recording = match arg_value("--record-feedback") {
+        Some(path) => {
+            let path = PathBuf::from(path);
+            let format = RecordFormat::from_path(&path).unwrap_or(RecordFormat::Csv);
+            log::info!("Recording robot feedback to {}", path.display());
+            let recorder = FeedbackRecorder::create(&path, format, &RecordedField::all())?;
+            Some(recorder.spawn(robot.feedback_hub().subscribe_samples()))
+        }
+        None => None,
+    };
+
+    // Demonstration sequences are taught and replayed without AERA
+    if let Some(name) = arg_value("--teach") {
+        return teach_trajectory(robot.as_mut(), &name);
+    }
+    if let Some(path) = arg_value("--replay") {
+        return replay_trajectory(robot.as_mut(), &PathBuf::from(path));
+    }
 
+    // Spawned once, the threads keep watching the feedback across main loop retries
+    let anomalies = CollisionMonitor::new(CollisionConfig::new()).spawn(robot.subscribe_feedback(), Some(robot.emergency_handle()?));
+    let io_events = IoWatcher::new(io, true).spawn(robot.subscribe_feedback());
     loop {
-        match run_main_loop(&mut robot) {
+        match run_main_loop(robot.as_mut(), end_effector.as_mut(), &watchdog, &anomalies, &io_events) {
             Ok(_) => break,
             Err(e) => {
                 log::error!("Error occurred in main loop {e:?}");
+                watchdog.trip("Main loop failed");
                 log::debug!("Trying to reconnect");
                 continue
             },
         }
     }
 
+    watchdog.shutdown();
+    if let Some(recording) = recording {
+        recording.stop()?;
+    }
+
     Ok(())
 }
 
-fn run_main_loop(robot: &mut RobotConn) -> anyhow::Result<()> {
-    let mut robot_feedback = RobotFeedbackConn::connect().expect("Failed to connect to robot feedback");
-    let feedback_data = Arc::new(Mutex::new(robot_feedback.receive_feedback()?));
-
+fn run_main_loop(robot: &mut dyn Robot, end_effector: &mut dyn EndEffector, watchdog: &Watchdog, anomalies: &Receiver<Anomaly>, io_events: &Receiver<IoEvent>) -> anyhow::Result<()> {
     log::info!("Connecting to AERA");
     let mut aera = AeraConn::connect("192.168.1.44")?;
     let mut properties = Properties::new();
     log::debug!("Wating for start message");
     aera.wait_for_start_message()?;
 
-    log::info!("Connecting to pixy");
-    let pixy = PixyCamera::init()?;
+    let frames = start_capture()?;
     let mut vision = VisionSystem::new();
-
-    {
-        log::info!("Getting initial feedback...");
-        let feedback_data = feedback_data.clone();
-        thread::spawn(move || {
-            run_feedback_loop(robot_feedback, feedback_data);
-        });
-    }
+    let mut alarms = AlarmSupervisor::new(AlarmConfig::new());
 
     log::info!("Starting main loop");
+    watchdog.arm();
     loop {
         sleep(Duration::from_secs(3));
 
         // Get data from camera
-        let frame = pixy.get_frame()?;
-        let objects = vision.process_frame(&frame)?;
+        let Some(captured) = frames.recv(FRAME_TIMEOUT)? else {
+            log::info!("No more frames, stopping");
+            return Ok(());
+        };
+        let capture_time = captured.time;
+        let objects = vision.process_frame(&captured.frame)?;
         println!("Recognized {}", objects.len());
         let mut cam_objs = vec![&mut properties.co1, &mut properties.co2, &mut properties.co3];
         if properties.h.holding.is_some() {
@@ -75,17 +134,32 @@ fn run_main_loop(robot: &mut RobotConn) -> anyhow::Result<()> {
         }
 
         // Get data from robot
-        let feedback_data = feedback_data.lock().unwrap();
-        let [x, y, z, r, ..] = feedback_data.tool_vector_actual;
-        properties.h.position = Vector4::new(x, y, z, r);
-        if (((feedback_data.digital_outputs >> 2) & 1)) != 0 && objects.len() == 0 {
+        for event in alarms.update(robot) {
+            log_alarm_event(&event);
+        }
+        for anomaly in anomalies.try_iter() {
+            log::error!("Robot anomaly detected: {anomaly}");
+        }
+        let feedback = robot.feedback();
+        for event in io_events.try_iter() {
+            log::debug!("IO {} changed to {}", event.name.unwrap_or_default(), event.state);
+        }
+        properties.h.position = robot.pose();
+        let holding = match end_effector.state(&feedback) {
+            EndEffectorState::Holding => true,
+            // Can't verify the grasp here, assume the object was picked up if it is no longer visible
+            EndEffectorState::Engaged => objects.len() == 0,
+            EndEffectorState::Released | EndEffectorState::Empty => false,
+        };
+        if holding {
             properties.h.holding = Some("co1".to_string());
         }
+        // Use the hand pose from when the frame was captured, the arm may have moved since
+        let capture_pose = robot.feedback_hub().pose_at(capture_time).unwrap_or(properties.h.position);
         for co in cam_objs.iter_mut().filter(|co| co.class != -1) {
-            co.approximate_pos = calculate_predicted_grab_pos(&properties.h.position, &co.position);
+            co.approximate_pos = calculate_predicted_grab_pos(&capture_pose, &co.position);
             log::debug!("Sending approximate cube pos ({}, {}, {}, {})", co.approximate_pos.x, co.approximate_pos.y, co.approximate_pos.z, co.approximate_pos.w);
         }
-        drop(feedback_data);
 
         // Send to AERA
         log::debug!("Sending hand position ({}, {}, {}, {})", properties.h.position.x, properties.h.position.y, properties.h.position.z, properties.h.position.w);
@@ -95,8 +169,13 @@ fn run_main_loop(robot: &mut RobotConn) -> anyhow::Result<()> {
         // Handle command from AERA
         log::debug!("Listening for command");
         let cmd = match aera.listen_for_command() {
-            Ok(Some(cmd)) => cmd,
+            Ok(Some(cmd)) => {
+                watchdog.heartbeat();
+                cmd
+            }
             Ok(None) => {
+                // The link is still up, AERA just had nothing to do
+                watchdog.heartbeat();
                 log::error!("Timed out waiting for command from AERA");
                 continue;
             }
@@ -108,27 +187,27 @@ fn run_main_loop(robot: &mut RobotConn) -> anyhow::Result<()> {
         match cmd {
             Command::EnableRobot => {
                 log::debug!("Got enable_robot command from AERA");
-                log_err(|| robot.enable_robot());
+                log_err(|| robot.enable());
             }
             Command::MovJ(x, y, z, r) => {
                 log::debug!("Got movj command from AERA to {x}, {y}, {z}, {r}");
-                log_err(|| robot.mov_j(x as f64, y as f64, z as f64, r as f64));
+                log_err(|| robot.move_to(&Vector4::new(x as f64, y as f64, z as f64, r as f64)));
             }
             Command::Move(x, y, z, r) => {
                 log::debug!("Got move (relative) command from AERA by {x}, {y}, {z}, {r}");
-                let pos = &properties.h.position;
-                log_err(|| robot.mov_j(pos.x + x, pos.y + y, pos.z + z, pos.w + r));
+                log_err(|| robot.move_relative(&Vector4::new(x, y, z, r)));
             }
             Command::Grab => {
                 log::debug!("Got grab command from AERA");
                 log_err(|| -> anyhow::Result<()> {
-                    let pos = properties.h.position + Vector4::new(0.0, 0.0, -137.0, 0.0);
-                    robot.mov_j(pos.x, pos.y, pos.z, pos.w)?;
-                    sleep(Duration::from_secs(1));
-                    robot.set_do(3, true)?;
-                    sleep(Duration::from_secs(1));
-                    let orig_pos = &properties.h.position;
-                    robot.mov_j(orig_pos.x, orig_pos.y, orig_pos.z, orig_pos.w)?;
+                    let pos = properties.h.position + end_effector.grasp_offset();
+                    // Wait until the arm has actually arrived instead of for a fixed time. The queue gives up on its own
+                    // if the arm gets stuck, so the watchdog is fed while it polls.
+                    MotionQueue::new(MotionQueueConfig::new())
+                        .push_move(pos, MotionParams::default())
+                        .run_with(robot, Duration::from_millis(20), |_| watchdog.heartbeat())?;
+                    end_effector.grasp(robot)?;
+                    robot.move_to(&properties.h.position)?;
 
                     Ok(())
                 });
@@ -136,7 +215,7 @@ fn run_main_loop(robot: &mut RobotConn) -> anyhow::Result<()> {
             Command::Release => {
                 log::debug!("Got release command from AERA");
                 log_err(|| -> anyhow::Result<()> {
-                    robot.set_do(3, false)?;
+                    end_effector.release(robot)?;
                     properties.h.holding = None;
 
                     Ok(())
@@ -167,20 +246,112 @@ fn log_err<T, E: fmt::Display>(f: impl FnOnce() -> Result<T, E>) {
     }
 }
 
-fn run_feedback_loop(mut robot_feedback_conn: RobotFeedbackConn, feedback: Arc<Mutex<FeedbackData>>) {
-    loop {
-        let res = match robot_feedback_conn.receive_feedback() {
-            Ok(feedback) => feedback,
-            Err(e) => {
-                log::error!("Error receiving feedback {e:?}");
-                sleep(Duration::from_secs(1));
-                continue;
-            }
+fn log_alarm_event(event: &AlarmEvent) {
+    match event {
+        AlarmEvent::Raised(ids) => log::error!("Robot alarm raised, controller errors {:?}, servo errors {:?}", ids.controller, ids.servo),
+        AlarmEvent::RecoveryAttempted { attempt } => log::warn!("Trying to clear robot alarm (attempt {attempt})"),
+        AlarmEvent::RecoveryFailed { attempts } => log::error!("Failed to clear robot alarm after {attempts} attempts"),
+        AlarmEvent::Cleared => log::info!("Robot alarm cleared"),
+    }
+}
+
+// The camera keeps only the newest frame, recorded and generated frames are processed one by one
+fn start_capture() -> anyhow::Result<FrameCapture> {
+    let (source, live) = open_frame_source()?;
+    let mut config = CaptureConfig::new();
+    if live {
+        config.queue_capacity = 1;
+    } else {
+        config.overflow = Overflow::Block;
+    }
+    FrameCapture::spawn(source, config)
+}
+
+// Pixy2 by default, or recorded footage with `--frames <dir, video or raw recording>` or generated frames with `--synthetic-frames`.
+// `--record-raw <path>` saves the raw Pixy2 frames for replay with `--frames`.
+// Also returns whether the source is live.
+fn open_frame_source() -> anyhow::Result<(Box<dyn FrameSource + Send>, bool)> {
+    if let Some(path) = arg_value("--frames") {
+        let path = PathBuf::from(path);
+        log::info!("Reading frames from {}", path.display());
+        let source: Box<dyn FrameSource + Send> = if path.is_dir() {
+            Box::new(ImageDirectorySource::open(&path, false)?)
+        } else if path.extension().is_some_and(|e| e == RAW_RECORDING_EXTENSION) {
+            Box::new(RawReplaySource::open(&path, false)?)
+        } else {
+            Box::new(VideoSource::open(&path, false)?)
         };
-        *feedback.lock().unwrap() = res;
+        return Ok((source, false));
+    }
+    if std::env::args().any(|a| a == "--synthetic-frames") {
+        log::info!("Using synthetic frames");
+        return Ok((Box::new(SyntheticSource::new(SyntheticConfig::new())), false));
+    }
+
+    log::info!("Connecting to pixy");
+    let mut camera = PixyCamera::init()?;
+    if let Some(path) = arg_value("--record-raw") {
+        log::info!("Recording raw frames to {path}");
+        camera.start_recording(&PathBuf::from(path))?;
+    }
+    if std::env::args().any(|a| a == "--fixed-lighting") {
+        lock_lighting(&mut camera)?;
+    }
+    Ok((Box::new(camera), true))
+}
+
+// Turns the lamps on, lets auto white balance and exposure adapt to them and then keeps the values
+// they settled on, so the HSV thresholds don't drift with the room lighting
+fn lock_lighting(camera: &mut PixyCamera) -> anyhow::Result<()> {
+    let mut settings = camera.settings().clone();
+    if let Some(brightness) = arg_value("--pixy-brightness") {
+        settings.brightness = brightness.parse()?;
+    }
+    settings.upper_lamp = true;
+    settings.lower_lamp = true;
+    camera.apply_settings(&settings)?;
+    for _ in 0..LIGHTING_SETTLE_FRAMES {
+        camera.get_frame()?;
+    }
+
+    camera.apply_settings(&CameraSettings::fixed_lighting(settings.brightness, None, None))?;
+    log::info!("Locked pixy white balance and exposure with brightness {}", settings.brightness);
+    Ok(())
+}
 
-        sleep(Duration::from_millis(10));
+fn teach_trajectory(robot: &mut dyn Robot, name: &str) -> anyhow::Result<()> {
+    robot.enable()?;
+    let mut session = TeachSession::start(robot, name, TeachConfig::new())?;
+    log::info!("Drag mode active, guide the arm by hand and press the record button to mark keyframes. Press enter to finish");
+    let (enter_tx, enter) = mpsc::channel();
+    thread::spawn(move || {
+        let _ = std::io::stdin().read_line(&mut String::new());
+        let _ = enter_tx.send(());
+    });
+    while let Err(RecvTimeoutError::Timeout) = enter.recv_timeout(TEACH_POLL_INTERVAL) {
+        session.poll();
     }
+
+    let trajectory = session.finish(robot)?;
+    let path = trajectory.save(&PathBuf::from(TRAJECTORY_DIR))?;
+    log::info!("Saved {} points ({:.1} s) to {}", trajectory.points.len(), trajectory.duration(), path.display());
+
+    Ok(())
+}
+
+fn replay_trajectory(robot: &mut dyn Robot, path: &PathBuf) -> anyhow::Result<()> {
+    let trajectory = Trajectory::load(path)?;
+    log::info!("Replaying trajectory {} with {} points", trajectory.name, trajectory.points.len());
+    robot.enable()?;
+    trajectory
+        .to_queue(PlaybackMode::Path { blend: 50 }, MotionQueueConfig::new())
+        .run(robot, Duration::from_millis(20))
+}
+
+fn arg_value(name: &str) -> Option<String> {
+    let mut args = std::env::args();
+    args.find(|a| a == name)?;
+    args.next()
 }
 
 fn setup_logging() {
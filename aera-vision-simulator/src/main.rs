@@ -3,6 +3,7 @@ use std::{collections::VecDeque, thread::sleep, time::Duration};
 use aera::{commands::Command, properties::Properties, AeraConn};
 use nalgebra::{Vector2, Vector4};
 use rand::{rngs::ThreadRng, thread_rng, Rng};
+use robot::{end_effector::{EndEffector, SuctionCup, SuctionCupConfig}, sim::SimulatedRobot, Robot};
 use simulated_cube::SimCube;
 
 pub mod simulated_cube;
@@ -17,7 +18,13 @@ fn main() -> anyhow::Result<()> {
     aera.wait_for_start_message()?;
 
     let mut sim_cube = SimCube::initial();
-    set_initial_state(&mut properties, &mut sim_cube);
+    let mut robot = SimulatedRobot::new(Vector4::new(240.0, 0.0, 0.0, 45.0));
+    // The simulated arm refuses moves while disabled, AERA may move before it sends EnableRobot
+    robot.enable()?;
+    let mut suction_config = SuctionCupConfig::new();
+    suction_config.actuation_delay = Duration::ZERO;
+    let mut end_effector = SuctionCup::new(suction_config);
+    set_initial_state(&mut properties, &mut sim_cube, &robot);
 
     let mut forced_commands = VecDeque::from([]);
 
@@ -72,28 +79,33 @@ fn main() -> anyhow::Result<()> {
         match cmd {
             Command::EnableRobot => {
                 log::debug!("Got enable_robot command from AERA");
+                robot.enable()?;
             }
             Command::MovJ(x, y, z, r) => {
                 log::debug!("Got movj command from AERA to {x}, {y}, {z}, {r}");
-                let old_pos = properties.h.position;
-                properties.h.position = Vector4::new(x as f64, y as f64, z as f64, r as f64);
+                let old_pos = robot.pose();
+                robot.move_to(&Vector4::new(x as f64, y as f64, z as f64, r as f64))?;
+                properties.h.position = robot.pose();
                 sim_cube.move_hand(&(properties.h.position - old_pos), &properties.h.position);
             }
             Command::Move(x, y, z, r) => {
                 log::debug!("Got move (relative) command from AERA by {x}, {y}, {z}, {r}");
                 let (x, y, z, r) = (x + random_noise(), y + random_noise(), z + random_noise(), r + random_noise());
                 log::debug!("Moving by {x}, {y}, {z}, {r}");
-                let current_pos = properties.h.position;
-                properties.h.position = Vector4::new(current_pos.x + x, current_pos.y + y, current_pos.z + z, current_pos.w + r);
-                sim_cube.move_hand(&Vector4::new(x, y, z, r), &properties.h.position);
+                let old_pos = robot.pose();
+                robot.move_relative(&Vector4::new(x, y, z, r))?;
+                properties.h.position = robot.pose();
+                sim_cube.move_hand(&(properties.h.position - old_pos), &properties.h.position);
             }
             Command::Grab => {
                 log::debug!("Got grab command from AERA");
+                end_effector.grasp(&mut robot)?;
                 properties.h.holding = Some("co1".to_string());
                 sim_cube.visible = false;
             }
             Command::Release => {
                 log::debug!("Got release command from AERA");
+                end_effector.release(&mut robot)?;
                 properties.h.holding = None;
                 properties.co1.approximate_pos.z = -140.0;
                 sim_cube.visible = true;
@@ -104,8 +116,8 @@ fn main() -> anyhow::Result<()> {
     }
 }
 
-fn set_initial_state(properties: &mut Properties, sim_cube: &mut SimCube) {
-    properties.h.position = Vector4::new(240.0, 0.0, 0.0, 45.0);
+fn set_initial_state(properties: &mut Properties, sim_cube: &mut SimCube, robot: &SimulatedRobot) {
+    properties.h.position = robot.pose();
 
     properties.co1.position = sim_cube.pos;
     properties.co1.class = 0;
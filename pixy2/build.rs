@@ -1,16 +1,63 @@
+use std::{env, path::PathBuf};
+
+// Locations can be overridden with environment variables:
+// PIXY2_DIR         checkout of https://github.com/charmedlabs/pixy2, defaults to ./pixy2
+// PIXY2_LIB_DIR     directory containing libpixy2.a, defaults to $PIXY2_DIR/build/libpixyusb2
+// LIBUSB_INCLUDE_DIR and LIBUSB_LIB_DIR when libusb-1.0 is not found with pkg-config
 fn main() {
-    cxx_build::bridge("src/lib.rs")
-        .compiler("g++")
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-env-changed=PIXY2_DIR");
+    println!("cargo:rerun-if-env-changed=PIXY2_LIB_DIR");
+    println!("cargo:rerun-if-env-changed=LIBUSB_INCLUDE_DIR");
+    println!("cargo:rerun-if-env-changed=LIBUSB_LIB_DIR");
+
+    // The mock backend is pure Rust
+    if env::var_os("CARGO_FEATURE_MOCK").is_some() {
+        return;
+    }
+
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let pixy2_dir = env::var_os("PIXY2_DIR").map(PathBuf::from).unwrap_or(manifest_dir.join("pixy2"));
+    let pixy2_lib_dir = env::var_os("PIXY2_LIB_DIR").map(PathBuf::from).unwrap_or(pixy2_dir.join("build/libpixyusb2"));
+    if !pixy2_lib_dir.join("libpixy2.a").exists() {
+        panic!(
+            "libpixy2.a not found in {}. Build libpixyusb2 (pixy2/scripts/build_libpixyusb2.sh), set PIXY2_DIR or PIXY2_LIB_DIR, or enable the `mock` feature",
+            pixy2_lib_dir.display()
+        );
+    }
+
+    let mut build = cxx_build::bridge("src/bridge.rs");
+    build
+        .file("src/bridge.cpp")
         .std("c++17")
-        .include("/opt/homebrew/include/")
-        .include("/opt/homebrew/include/libusb-1.0/")
-        .include("/Users/gudmundur/Projects/HR/Thesis/aera-vision-module/pixy2/pixy2/src/host/libpixyusb2/include")
-        .include("/Users/gudmundur/Projects/HR/Thesis/aera-vision-module/pixy2/pixy2/src/host/arduino/libraries/Pixy2")
-        .include("/Users/gudmundur/Projects/HR/Thesis/aera-vision-module/pixy2/src")
-        .object("/Users/gudmundur/Projects/HR/Thesis/aera-vision-module/pixy2/src/bridge.o")
-        .compile("pixy2");
-    
+        .include(manifest_dir.join("src"))
+        .include(pixy2_dir.join("src/host/libpixyusb2/include"))
+        .include(pixy2_dir.join("src/host/arduino/libraries/Pixy2"));
+
+    // Link flags are printed after compiling, so they come after the libraries that depend on them
+    let mut link_search = vec![pixy2_lib_dir];
+    match pkg_config::Config::new().cargo_metadata(false).probe("libusb-1.0") {
+        Ok(libusb) => {
+            for path in libusb.include_paths {
+                build.include(path);
+            }
+            link_search.extend(libusb.link_paths);
+        }
+        Err(e) => {
+            let include_dir = env::var_os("LIBUSB_INCLUDE_DIR")
+                .unwrap_or_else(|| panic!("libusb-1.0 not found with pkg-config ({e}), set LIBUSB_INCLUDE_DIR and LIBUSB_LIB_DIR"));
+            build.include(include_dir);
+            link_search.extend(env::var_os("LIBUSB_LIB_DIR").map(PathBuf::from));
+        }
+    }
+    build.compile("pixy2-bridge");
+
+    for path in link_search {
+        println!("cargo:rustc-link-search=native={}", path.display());
+    }
+    println!("cargo:rustc-link-lib=static=pixy2");
     println!("cargo:rustc-link-lib=usb-1.0");
-    println!("cargo:rerun-if-changed=src/main.rs");
-    println!("cargo:rerun-if-changed=bridge.cpp");
-}
\ No newline at end of file
+    println!("cargo:rerun-if-changed=src/bridge.rs");
+    println!("cargo:rerun-if-changed=src/bridge.cpp");
+    println!("cargo:rerun-if-changed=src/bridge.h");
+}
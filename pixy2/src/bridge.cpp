@@ -1,4 +1,5 @@
 #include "bridge.h"
+#include "pixy2/src/bridge.rs.h"
 #include <iostream>
 
 Pixy2 pixy_instance;
@@ -13,10 +14,135 @@ void set_lamp (int upper, int lower)
   pixy_instance.setLamp (upper, lower);
 }
 
+// Reopens the USB connection, e.g. after the camera was unplugged
+int reinit()
+{
+  pixy_instance.m_link.close();
+  return pixy_instance.init();
+}
+
 int stop() {
   return pixy_instance.m_link.stop();
 }
 
+int resume() {
+  return pixy_instance.m_link.resume();
+}
+
 int get_raw_frame(uint8_t **bayerFrame) {
   return pixy_instance.m_link.getRawFrame(bayerFrame);
-}
\ No newline at end of file
+}
+
+// Same request as getRawFrame, but with the sensor mode and window chosen by the caller
+int get_raw_frame_window(uint8_t mode, uint16_t x, uint16_t y, uint16_t width, uint16_t height, uint8_t **bayerFrame) {
+  int32_t response;
+  uint32_t fourcc;
+  int8_t renderflags;
+  uint16_t frameWidth, frameHeight;
+  uint32_t numPixels;
+  int result = pixy_instance.m_link.callChirp("cam_getFrame", UINT8(mode), UINT16(x), UINT16(y), UINT16(width), UINT16(height), END_OUT_ARGS,
+    &response, &fourcc, &renderflags, &frameWidth, &frameHeight, &numPixels, bayerFrame, END_IN_ARGS);
+  if (result < 0) {
+    return result;
+  }
+  if (response < 0) {
+    return response;
+  }
+  if (frameWidth != width || frameHeight != height) {
+    return BRIDGE_RESULT_FRAME_SIZE;
+  }
+  return numPixels >= (uint32_t)width * height ? 0 : BRIDGE_RESULT_SHORT_FRAME;
+}
+
+int set_camera_brightness(uint8_t brightness) {
+  return pixy_instance.setCameraBrightness(brightness);
+}
+
+// Camera parameters without a function in Pixy2, set through the same chirp calls PixyMon uses
+static int call_setter(const char *name, uint32_t value, bool wide) {
+  int32_t response;
+  int result = wide
+    ? pixy_instance.m_link.callChirp(name, UINT32(value), END_OUT_ARGS, &response, END_IN_ARGS)
+    : pixy_instance.m_link.callChirp(name, UINT8(value), END_OUT_ARGS, &response, END_IN_ARGS);
+  return result < 0 ? result : response;
+}
+
+int set_auto_white_balance(bool enabled) {
+  return call_setter("cam_setAWB", enabled, false);
+}
+
+int set_white_balance_value(uint32_t value) {
+  return call_setter("cam_setWBV", value, true);
+}
+
+int set_auto_exposure(bool enabled) {
+  return call_setter("cam_setAEC", enabled, false);
+}
+
+int set_exposure_value(uint32_t value) {
+  return call_setter("cam_setECV", value, true);
+}
+
+int get_blocks(bool wait, uint8_t sigmap, uint8_t max_blocks, rust::Vec<CccBlock> &blocks) {
+  int result = pixy_instance.ccc.getBlocks(wait, sigmap, max_blocks);
+  if (result < 0) {
+    return result;
+  }
+
+  blocks.clear();
+  for (int i = 0; i < pixy_instance.ccc.numBlocks; i++) {
+    const Block &block = pixy_instance.ccc.blocks[i];
+    blocks.push_back(CccBlock {
+      block.m_signature, block.m_x, block.m_y, block.m_width, block.m_height,
+      block.m_angle, block.m_index, block.m_age
+    });
+  }
+  return result;
+}
+
+int change_prog(rust::Str name) {
+  return pixy_instance.changeProg(std::string(name).c_str());
+}
+
+int set_servos(uint16_t pan, uint16_t tilt) {
+  return pixy_instance.setServos(pan, tilt);
+}
+
+// Returns the LINE_* bits of the features that were received, like getMainFeatures
+int get_line_features(bool main, uint8_t features, bool wait, rust::Vec<LineVector> &vectors, rust::Vec<LineIntersection> &intersections, rust::Vec<LineBarcode> &barcodes) {
+  int result = main
+    ? pixy_instance.line.getMainFeatures(features, wait)
+    : pixy_instance.line.getAllFeatures(features, wait);
+  if (result < 0) {
+    return result;
+  }
+
+  vectors.clear();
+  intersections.clear();
+  barcodes.clear();
+  if (result & LINE_VECTOR) {
+    for (int i = 0; i < pixy_instance.line.numVectors; i++) {
+      const Vector &vector = pixy_instance.line.vectors[i];
+      vectors.push_back(LineVector {
+        vector.m_x0, vector.m_y0, vector.m_x1, vector.m_y1, vector.m_index, vector.m_flags
+      });
+    }
+  }
+  if (result & LINE_INTERSECTION) {
+    for (int i = 0; i < pixy_instance.line.numIntersections; i++) {
+      const Intersection &intersection = pixy_instance.line.intersections[i];
+      rust::Vec<LineIntersectionLine> lines;
+      for (int j = 0; j < intersection.m_n && j < LINE_MAX_INTERSECTION_LINES; j++) {
+        lines.push_back(LineIntersectionLine { intersection.m_intLines[j].m_index, intersection.m_intLines[j].m_angle });
+      }
+      intersections.push_back(LineIntersection { intersection.m_x, intersection.m_y, std::move(lines) });
+    }
+  }
+  if (result & LINE_BARCODE) {
+    for (int i = 0; i < pixy_instance.line.numBarcodes; i++) {
+      const Barcode &barcode = pixy_instance.line.barcodes[i];
+      barcodes.push_back(LineBarcode { barcode.m_x, barcode.m_y, barcode.m_flags, barcode.m_code });
+    }
+  }
+  return result;
+}
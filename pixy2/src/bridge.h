@@ -1,9 +1,33 @@
 #pragma once
 #include <vector>
 #include "libpixyusb2.h"
+#include "rust/cxx.h"
 #include <memory>
 
+// Returned when the camera sent a frame of a different size than requested, PixyError::FrameSize on the Rust side
+#define BRIDGE_RESULT_FRAME_SIZE -100
+// Returned when fewer pixels arrived than the frame has, e.g. a cut off USB transfer, PixyError::ShortFrame on the Rust side
+#define BRIDGE_RESULT_SHORT_FRAME -101
+
+// Defined by the generated cxx bridge
+struct CccBlock;
+struct LineVector;
+struct LineIntersection;
+struct LineBarcode;
+
 int init();
+int reinit();
 void set_lamp(int upper, int lower);
 int stop();
-int get_raw_frame(uint8_t **bayerFrame);
\ No newline at end of file
+int resume();
+int get_raw_frame(uint8_t **bayerFrame);
+int get_raw_frame_window(uint8_t mode, uint16_t x, uint16_t y, uint16_t width, uint16_t height, uint8_t **bayerFrame);
+int set_camera_brightness(uint8_t brightness);
+int set_auto_white_balance(bool enabled);
+int set_white_balance_value(uint32_t value);
+int set_auto_exposure(bool enabled);
+int set_exposure_value(uint32_t value);
+int get_blocks(bool wait, uint8_t sigmap, uint8_t max_blocks, rust::Vec<CccBlock> &blocks);
+int change_prog(rust::Str name);
+int set_servos(uint16_t pan, uint16_t tilt);
+int get_line_features(bool main, uint8_t features, bool wait, rust::Vec<LineVector> &vectors, rust::Vec<LineIntersection> &intersections, rust::Vec<LineBarcode> &barcodes);
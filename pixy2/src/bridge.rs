@@ -0,0 +1,65 @@
+#[allow(unused_imports)]
+#[cxx::bridge]
+pub mod ffi {
+    // Copy of Block from Pixy2CCC.h, x and y are the center of the block
+    struct CccBlock {
+        signature: u16,
+        x: u16,
+        y: u16,
+        width: u16,
+        height: u16,
+        angle: i16,
+        index: u8,
+        age: u8,
+    }
+
+    // Copies of Vector, IntersectionLine, Intersection and Barcode from Pixy2Line.h.
+    // Coordinates are in the 79x52 line tracking grid.
+    struct LineVector {
+        x0: u8,
+        y0: u8,
+        x1: u8,
+        y1: u8,
+        index: u8,
+        flags: u8,
+    }
+
+    struct LineIntersectionLine {
+        index: u8,
+        angle: i16,
+    }
+
+    struct LineIntersection {
+        x: u8,
+        y: u8,
+        lines: Vec<LineIntersectionLine>,
+    }
+
+    struct LineBarcode {
+        x: u8,
+        y: u8,
+        flags: u8,
+        code: u8,
+    }
+
+    unsafe extern "C++" {
+        include!("bridge.h");
+
+        fn init() -> i32;
+        fn reinit() -> i32;
+        fn set_lamp(upper: i32, lower: i32);
+        fn stop() -> i32;
+        fn resume() -> i32;
+        unsafe fn get_raw_frame(bayer_frame: *mut *mut u8) -> i32;
+        unsafe fn get_raw_frame_window(mode: u8, x: u16, y: u16, width: u16, height: u16, bayer_frame: *mut *mut u8) -> i32;
+        fn set_camera_brightness(brightness: u8) -> i32;
+        fn set_auto_white_balance(enabled: bool) -> i32;
+        fn set_white_balance_value(value: u32) -> i32;
+        fn set_auto_exposure(enabled: bool) -> i32;
+        fn set_exposure_value(value: u32) -> i32;
+        fn get_blocks(wait: bool, sigmap: u8, max_blocks: u8, blocks: &mut Vec<CccBlock>) -> i32;
+        fn change_prog(name: &str) -> i32;
+        fn set_servos(pan: u16, tilt: u16) -> i32;
+        fn get_line_features(main: bool, features: u8, wait: bool, vectors: &mut Vec<LineVector>, intersections: &mut Vec<LineIntersection>, barcodes: &mut Vec<LineBarcode>) -> i32;
+    }
+}
@@ -0,0 +1,108 @@
+use anyhow::bail;
+
+use crate::{PIXY2_BAYER_FRAME_BUFFER_SIZE, PIXY2_RAW_FRAME_HEIGHT, PIXY2_RAW_FRAME_WIDTH};
+
+// Sensor readout mode of cam_getFrame, the lower resolutions bin neighbouring pixels together
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resolution {
+    // Mode used by getRawFrame, the 316x208 frame covers the whole field of view
+    Low,
+    // Twice the detail, a 316x208 frame covers half the field of view
+    Medium,
+    // Full sensor resolution
+    Full,
+}
+
+impl Resolution {
+    pub fn mode(&self) -> u8 {
+        match self {
+            Resolution::Low => 0x21,
+            Resolution::Medium => 0x11,
+            Resolution::Full => 0x01,
+        }
+    }
+
+    pub fn from_mode(mode: u8) -> Option<Resolution> {
+        [Resolution::Low, Resolution::Medium, Resolution::Full].into_iter().find(|r| r.mode() == mode)
+    }
+}
+
+// Part of the sensor image that is sent, in pixels of the selected resolution.
+// The camera only buffers 316x208 pixels, so higher resolutions crop instead of showing more.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FrameWindow {
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+}
+
+impl FrameWindow {
+    // The frame getRawFrame returns
+    pub fn full() -> FrameWindow {
+        FrameWindow { x: 0, y: 0, width: PIXY2_RAW_FRAME_WIDTH as u16, height: PIXY2_RAW_FRAME_HEIGHT as u16 }
+    }
+
+    pub fn pixels(&self) -> usize {
+        self.width as usize * self.height as usize
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CameraSettings {
+    pub upper_lamp: bool,
+    pub lower_lamp: bool,
+    pub brightness: u8,
+    pub auto_white_balance: bool,
+    // Raw white balance register as shown in PixyMon, only used without auto white balance.
+    // None keeps the current value, e.g. the one auto white balance settled on.
+    pub white_balance: Option<u32>,
+    pub auto_exposure: bool,
+    // Raw exposure and gain register as shown in PixyMon, only used without auto exposure
+    pub exposure: Option<u32>,
+    pub resolution: Resolution,
+    pub window: FrameWindow,
+}
+
+impl CameraSettings {
+    // Camera defaults after power up
+    pub fn new() -> CameraSettings {
+        CameraSettings {
+            upper_lamp: false,
+            lower_lamp: false,
+            brightness: 80,
+            auto_white_balance: true,
+            white_balance: None,
+            auto_exposure: true,
+            exposure: None,
+            resolution: Resolution::Low,
+            window: FrameWindow::full(),
+        }
+    }
+
+    // Lamps on and white balance and exposure locked, so colors don't drift between frames
+    // and HSV thresholds stay valid
+    pub fn fixed_lighting(brightness: u8, white_balance: Option<u32>, exposure: Option<u32>) -> CameraSettings {
+        CameraSettings {
+            upper_lamp: true,
+            lower_lamp: true,
+            brightness,
+            auto_white_balance: false,
+            white_balance,
+            auto_exposure: false,
+            exposure,
+            ..CameraSettings::new()
+        }
+    }
+
+    pub fn validate(&self) -> anyhow::Result<()> {
+        let w = self.window;
+        if w.width < 2 || w.height < 2 || w.width % 2 != 0 || w.height % 2 != 0 || w.x % 2 != 0 || w.y % 2 != 0 {
+            bail!("Frame window {}x{} at ({}, {}) has to be even and at least 2x2", w.width, w.height, w.x, w.y);
+        }
+        if w.pixels() > PIXY2_BAYER_FRAME_BUFFER_SIZE {
+            bail!("Frame window {}x{} is larger than the camera frame buffer", w.width, w.height);
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,189 @@
+use std::{collections::VecDeque, sync::{atomic::{AtomicBool, Ordering}, Arc, Condvar, Mutex}, thread::{self, JoinHandle}, time::{Duration, Instant}};
+
+use anyhow::{anyhow, bail};
+
+use crate::{frame::Frame, frame_source::FrameSource};
+
+#[derive(Debug, Clone)]
+pub struct CapturedFrame {
+    pub frame: Frame,
+    // Counts every frame the source produced, gaps mean frames were dropped
+    pub sequence: u64,
+    // When the frame was requested from the source. The camera exposes the frame after the request
+    // arrives, so this is the closest host time to the exposure.
+    pub time: Instant,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Overflow {
+    // Drop the oldest queued frame, for live cameras where only recent frames matter
+    DropOldest,
+    // Wait until a frame is taken, for recordings where every frame should be processed
+    Block,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaptureConfig {
+    pub queue_capacity: usize,
+    pub overflow: Overflow,
+}
+
+impl CaptureConfig {
+    pub fn new() -> CaptureConfig {
+        CaptureConfig {
+            queue_capacity: 8,
+            overflow: Overflow::DropOldest,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct CaptureStats {
+    pub captured: u64,
+    pub dropped: u64,
+}
+
+struct CaptureState {
+    latest: Option<CapturedFrame>,
+    queue: VecDeque<CapturedFrame>,
+    stats: CaptureStats,
+    // Set when the source ran out of frames or failed
+    finished: bool,
+    error: Option<String>,
+}
+
+struct Shared {
+    state: Mutex<CaptureState>,
+    // Notified when a frame is queued or taken and when the worker stops
+    changed: Condvar,
+    stop: AtomicBool,
+}
+
+// Grabs frames from a source on a background thread as fast as it delivers them
+pub struct FrameCapture {
+    shared: Arc<Shared>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl FrameCapture {
+    pub fn spawn<S: FrameSource + Send + 'static>(source: S, config: CaptureConfig) -> anyhow::Result<FrameCapture> {
+        if config.queue_capacity == 0 {
+            bail!("Capture queue capacity has to be at least 1");
+        }
+        let shared = Arc::new(Shared {
+            state: Mutex::new(CaptureState {
+                latest: None,
+                queue: VecDeque::with_capacity(config.queue_capacity),
+                stats: CaptureStats::default(),
+                finished: false,
+                error: None,
+            }),
+            changed: Condvar::new(),
+            stop: AtomicBool::new(false),
+        });
+
+        let thread = {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("frame-capture".to_string())
+                .spawn(move || capture_loop(source, config, &shared))?
+        };
+
+        Ok(FrameCapture { shared, thread: Some(thread) })
+    }
+
+    // Newest captured frame, whether or not it was taken from the queue
+    pub fn latest(&self) -> Option<CapturedFrame> {
+        self.shared.state.lock().unwrap().latest.clone()
+    }
+
+    // Takes the oldest queued frame, waiting up to `timeout` for one.
+    // None once the source has no more frames and the queue is empty.
+    pub fn recv(&self, timeout: Duration) -> anyhow::Result<Option<CapturedFrame>> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.shared.state.lock().unwrap();
+        loop {
+            if let Some(frame) = state.queue.pop_front() {
+                self.shared.changed.notify_all();
+                return Ok(Some(frame));
+            }
+            if let Some(error) = &state.error {
+                return Err(anyhow!("Frame capture failed: {error}"));
+            }
+            if state.finished {
+                return Ok(None);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                bail!("No frame captured within {timeout:?}");
+            }
+            state = self.shared.changed.wait_timeout(state, deadline - now).unwrap().0;
+        }
+    }
+
+    // Takes all queued frames without waiting
+    pub fn drain(&self) -> Vec<CapturedFrame> {
+        let frames = self.shared.state.lock().unwrap().queue.drain(..).collect();
+        self.shared.changed.notify_all();
+        frames
+    }
+
+    pub fn stats(&self) -> CaptureStats {
+        self.shared.state.lock().unwrap().stats
+    }
+
+    pub fn stop(&mut self) {
+        {
+            // Holding the lock so a worker blocked on a full queue can't miss the notification
+            let _state = self.shared.state.lock().unwrap();
+            self.shared.stop.store(true, Ordering::Relaxed);
+            self.shared.changed.notify_all();
+        }
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Frame capture thread panicked");
+            }
+        }
+    }
+}
+
+impl Drop for FrameCapture {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+fn capture_loop<S: FrameSource>(mut source: S, config: CaptureConfig, shared: &Shared) {
+    let mut sequence = 0;
+    while !shared.stop.load(Ordering::Relaxed) {
+        let time = Instant::now();
+        let frame = match source.next_frame() {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                shared.state.lock().unwrap().error = Some(format!("{e:#}"));
+                break;
+            }
+        };
+        let captured = CapturedFrame { frame, sequence, time };
+        sequence += 1;
+
+        let mut state = shared.state.lock().unwrap();
+        if config.overflow == Overflow::Block {
+            while state.queue.len() >= config.queue_capacity && !shared.stop.load(Ordering::Relaxed) {
+                state = shared.changed.wait(state).unwrap();
+            }
+        }
+        if state.queue.len() >= config.queue_capacity {
+            state.queue.pop_front();
+            state.stats.dropped += 1;
+        }
+        state.latest = Some(captured.clone());
+        state.queue.push_back(captured);
+        state.stats.captured += 1;
+        shared.changed.notify_all();
+    }
+
+    shared.state.lock().unwrap().finished = true;
+    shared.changed.notify_all();
+}
@@ -0,0 +1,60 @@
+use crate::ffi::CccBlock;
+
+// Object found by the color connected components program running on the camera
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Block {
+    // 1-7 for color signatures, larger values are color codes with the signatures as octal digits
+    pub signature: u16,
+    // Center of the block in frame pixels (316x208)
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    // Only set for color codes (deg)
+    pub angle: i16,
+    // Stays the same while the camera tracks the same object
+    pub tracking_index: u8,
+    // Number of frames the object has been tracked, stops at 255
+    pub age: u8,
+}
+
+impl Block {
+    pub fn is_color_code(&self) -> bool {
+        self.signature > 7
+    }
+
+    // Top left and bottom right corner
+    pub fn bounds(&self) -> ((i32, i32), (i32, i32)) {
+        let (x, y) = (self.x as i32 - self.width as i32 / 2, self.y as i32 - self.height as i32 / 2);
+        ((x, y), (x + self.width as i32, y + self.height as i32))
+    }
+}
+
+impl From<&CccBlock> for Block {
+    fn from(b: &CccBlock) -> Block {
+        Block {
+            signature: b.signature,
+            x: b.x,
+            y: b.y,
+            width: b.width,
+            height: b.height,
+            angle: b.angle,
+            tracking_index: b.index,
+            age: b.age,
+        }
+    }
+}
+
+// Set of color signatures (1-7) to report, color codes are reported for the signatures they contain
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SignatureMask(pub u8);
+
+impl SignatureMask {
+    pub fn all() -> SignatureMask {
+        SignatureMask(0xff)
+    }
+
+    pub fn only(signatures: &[u8]) -> SignatureMask {
+        SignatureMask(signatures.iter().filter(|s| (1..=7).contains(*s)).fold(0, |mask, s| mask | 1 << (s - 1)))
+    }
+}
@@ -0,0 +1,292 @@
+use anyhow::bail;
+use opencv::{core::{Mat, Scalar, CV_8UC3}, prelude::*};
+
+// The Pixy2 sensor pattern starts with blue in the top left corner:
+//   B G B G
+//   G R G R
+// Even rows hold blue and green, odd rows green and red.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DemosaicMethod {
+    // Average of the nearest samples of each color, fast but blurs edges and adds color fringes
+    Bilinear,
+    // Bilinear corrected with the gradient of the sampled channel (Malvar, He and Cutler 2004)
+    MalvarHeCutler,
+}
+
+// Border added around the raw image so every kernel can be applied without bounds checks
+const PAD: usize = 2;
+
+// Demosaics a raw Bayer frame into a new BGR Mat
+pub fn demosaic(bayer: &[u8], width: usize, height: usize, method: DemosaicMethod) -> anyhow::Result<Mat> {
+    let mut mat = Mat::new_rows_cols_with_default(height as i32, width as i32, CV_8UC3, Scalar::all(0.0))?;
+    demosaic_into(bayer, width, height, method, mat.data_bytes_mut()?)?;
+    Ok(mat)
+}
+
+// Writes interleaved BGR pixels to `bgr`, which has to hold width * height * 3 bytes
+pub fn demosaic_into(bayer: &[u8], width: usize, height: usize, method: DemosaicMethod, bgr: &mut [u8]) -> anyhow::Result<()> {
+    if width < 2 || height < 2 || width % 2 != 0 || height % 2 != 0 {
+        bail!("Bayer frame size {width}x{height} has to be even and at least 2x2");
+    }
+    if bayer.len() != width * height {
+        bail!("Bayer frame has {} bytes, expected {}", bayer.len(), width * height);
+    }
+    if bgr.len() != width * height * 3 {
+        bail!("Output buffer has {} bytes, expected {}", bgr.len(), width * height * 3);
+    }
+
+    let padded = Padded::new(bayer, width, height);
+    let mut kernels = Kernels::new(width);
+    for y in 0..height {
+        let out = &mut bgr[y * width * 3..(y + 1) * width * 3];
+        match method {
+            DemosaicMethod::Bilinear => bilinear_row(&padded, y, &mut kernels, out),
+            DemosaicMethod::MalvarHeCutler => malvar_row(&padded, y, &mut kernels, out),
+        }
+    }
+
+    Ok(())
+}
+
+// Raw image with a mirrored border. Mirroring around the edge pixel keeps the Bayer pattern intact,
+// so border pixels are interpolated from real samples of the right color.
+struct Padded {
+    data: Vec<i32>,
+    stride: usize,
+    width: usize,
+}
+
+impl Padded {
+    fn new(bayer: &[u8], width: usize, height: usize) -> Padded {
+        let stride = width + 2 * PAD;
+        // Reflecting again until the index is inside keeps the parity on images narrower than the border
+        let mirror = |i: isize, len: usize| -> usize {
+            let last = len as isize - 1;
+            let mut i = i;
+            while i < 0 || i > last {
+                i = if i < 0 { -i } else { 2 * last - i };
+            }
+            i as usize
+        };
+        let mut data = Vec::with_capacity(stride * (height + 2 * PAD));
+        for py in 0..height + 2 * PAD {
+            let row = &bayer[mirror(py as isize - PAD as isize, height) * width..][..width];
+            let border = |px: isize| row[mirror(px, width)] as i32;
+            data.extend((-(PAD as isize)..0).map(border));
+            data.extend(row.iter().map(|v| *v as i32));
+            data.extend((width as isize..(width + PAD) as isize).map(border));
+        }
+        Padded { data, stride, width }
+    }
+
+    // Image row `y` offset by `dy` rows and `dx` columns, the same length as an image row
+    fn row(&self, y: usize, dy: isize, dx: isize) -> &[i32] {
+        let start = (y as isize + PAD as isize + dy) as usize * self.stride + (PAD as isize + dx) as usize;
+        &self.data[start..start + self.width]
+    }
+}
+
+// Every kernel is evaluated for the whole row and the results are picked by the Bayer pattern afterwards.
+// The kernels share sums of neighbour pairs, and every loop zips a few equally long rows so the compiler can
+// vectorize it without bounds checks.
+struct Kernels {
+    // left + right, up + down, left2 + right2, up2 + down2 and the four diagonal neighbours
+    horiz: Vec<i32>,
+    vert: Vec<i32>,
+    horiz2: Vec<i32>,
+    vert2: Vec<i32>,
+    diag: Vec<i32>,
+    k: [Vec<i32>; 4],
+}
+
+impl Kernels {
+    fn new(width: usize) -> Kernels {
+        Kernels {
+            horiz: vec![0; width],
+            vert: vec![0; width],
+            horiz2: vec![0; width],
+            vert2: vec![0; width],
+            diag: vec![0; width],
+            k: std::array::from_fn(|_| vec![0; width]),
+        }
+    }
+
+    // Sums for row `y`, the ones two pixels away only when `far` is set
+    fn sum_neighbours(&mut self, p: &Padded, y: usize, far: bool) {
+        add_rows(&mut self.horiz, p.row(y, 0, -1), p.row(y, 0, 1));
+        add_rows(&mut self.vert, p.row(y, -1, 0), p.row(y, 1, 0));
+        add_rows(&mut self.diag, p.row(y, -1, -1), p.row(y, -1, 1));
+        for ((d, a), b) in self.diag.iter_mut().zip(p.row(y, 1, -1)).zip(p.row(y, 1, 1)) {
+            *d += a + b;
+        }
+        if far {
+            add_rows(&mut self.horiz2, p.row(y, 0, -2), p.row(y, 0, 2));
+            add_rows(&mut self.vert2, p.row(y, -2, 0), p.row(y, 2, 0));
+        }
+    }
+}
+
+fn add_rows(out: &mut [i32], a: &[i32], b: &[i32]) {
+    for ((o, a), b) in out.iter_mut().zip(a).zip(b) {
+        *o = a + b;
+    }
+}
+
+fn clamp(v: i32) -> u8 {
+    v.clamp(0, 255) as u8
+}
+
+fn bilinear_row(p: &Padded, y: usize, kernels: &mut Kernels, out: &mut [u8]) {
+    kernels.sum_neighbours(p, y, false);
+    let Kernels { horiz, vert, diag, k: [cross_avg, diag_avg, horiz_avg, vert_avg], .. } = kernels;
+
+    for ((c, h), v) in cross_avg.iter_mut().zip(horiz.iter()).zip(vert.iter()) {
+        *c = (h + v + 2) >> 2;
+    }
+    for (a, d) in diag_avg.iter_mut().zip(diag.iter()) {
+        *a = (d + 2) >> 2;
+    }
+    for (a, h) in horiz_avg.iter_mut().zip(horiz.iter()) {
+        *a = (h + 1) >> 1;
+    }
+    for (a, v) in vert_avg.iter_mut().zip(vert.iter()) {
+        *a = (v + 1) >> 1;
+    }
+
+    let mid = p.row(y, 0, 0);
+    if y % 2 == 0 {
+        // B at even columns, G with blue to the left and right at odd columns
+        interleave(out, [mid, cross_avg, diag_avg], [horiz_avg, mid, vert_avg]);
+    } else {
+        // G with red to the left and right at even columns, R at odd columns
+        interleave(out, [vert_avg, mid, horiz_avg], [diag_avg, cross_avg, mid]);
+    }
+}
+
+fn malvar_row(p: &Padded, y: usize, kernels: &mut Kernels, out: &mut [u8]) {
+    kernels.sum_neighbours(p, y, true);
+    let Kernels { horiz, vert, horiz2, vert2, diag, k: [green_at_rb, horizontal_at_g, vertical_at_g, diagonal_at_rb] } = kernels;
+    let mid = p.row(y, 0, 0);
+
+    // Kernels from the paper scaled by 16 so the half coefficients are integers
+    let near = mid.iter().zip(horiz.iter()).zip(vert.iter());
+    let far = horiz2.iter().zip(vert2.iter()).zip(diag.iter());
+    for ((g, rb), (((m, h), v), ((h2, v2), d))) in green_at_rb.iter_mut().zip(diagonal_at_rb.iter_mut()).zip(near.clone().zip(far.clone())) {
+        *g = (8 * m + 4 * (h + v) - 2 * (h2 + v2) + 8) >> 4;
+        // Red at a blue pixel or blue at a red pixel
+        *rb = (12 * m + 4 * d - 3 * (h2 + v2) + 8) >> 4;
+    }
+    for ((hz, vt), (((m, h), v), ((h2, v2), d))) in horizontal_at_g.iter_mut().zip(vertical_at_g.iter_mut()).zip(near.zip(far)) {
+        // Missing color whose samples are to the left and right of a green pixel
+        *hz = (10 * m + 8 * h - 2 * (h2 + d) + v2 + 8) >> 4;
+        // Missing color whose samples are above and below a green pixel
+        *vt = (10 * m + 8 * v - 2 * (v2 + d) + h2 + 8) >> 4;
+    }
+
+    if y % 2 == 0 {
+        interleave(out, [mid, green_at_rb, diagonal_at_rb], [horizontal_at_g, mid, vertical_at_g]);
+    } else {
+        interleave(out, [vertical_at_g, mid, horizontal_at_g], [diagonal_at_rb, green_at_rb, mid]);
+    }
+}
+
+// Writes BGR pixels, taking the channels of even columns from `even` and of odd columns from `odd`
+fn interleave(out: &mut [u8], even: [&[i32]; 3], odd: [&[i32]; 3]) {
+    let [b0, g0, r0] = even.map(|c| c.chunks_exact(2));
+    let [b1, g1, r1] = odd.map(|c| c.chunks_exact(2));
+    let columns = b0.zip(g0).zip(r0).zip(b1.zip(g1).zip(r1));
+    for (pair, (((b0, g0), r0), ((b1, g1), r1))) in out.chunks_exact_mut(6).zip(columns) {
+        pair.copy_from_slice(&[clamp(b0[0]), clamp(g0[0]), clamp(r0[0]), clamp(b1[1]), clamp(g1[1]), clamp(r1[1])]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::raw_recording::RawReplaySource;
+
+    use super::*;
+
+    const WIDTH: usize = 6;
+    const HEIGHT: usize = 4;
+    const FIXTURE: [u8; WIDTH * HEIGHT] = [
+        10, 200, 30, 180, 50, 160,
+        220, 40, 200, 60, 180, 80,
+        70, 140, 90, 120, 110, 100,
+        160, 100, 140, 120, 120, 140,
+    ];
+
+    fn run(bayer: &[u8], width: usize, height: usize, method: DemosaicMethod) -> Vec<u8> {
+        let mut bgr = vec![0; width * height * 3];
+        demosaic_into(bayer, width, height, method, &mut bgr).unwrap();
+        bgr
+    }
+
+    #[test]
+    fn bilinear_matches_reference() {
+        let expected: [u8; WIDTH * HEIGHT * 3] = [
+            10, 210, 40, 20, 200, 40, 30, 195, 50, 40, 180, 60, 50, 175, 70, 50, 160, 80,
+            40, 220, 40, 50, 190, 40, 60, 200, 50, 70, 170, 60, 80, 180, 70, 80, 155, 80,
+            70, 165, 70, 80, 140, 70, 90, 150, 80, 100, 120, 90, 110, 130, 100, 110, 100, 110,
+            70, 160, 100, 80, 145, 100, 90, 140, 110, 100, 125, 120, 110, 120, 130, 110, 110, 140,
+        ];
+        assert_eq!(run(&FIXTURE, WIDTH, HEIGHT, DemosaicMethod::Bilinear), expected);
+    }
+
+    #[test]
+    fn malvar_matches_reference() {
+        let expected: [u8; WIDTH * HEIGHT * 3] = [
+            10, 190, 10, 10, 200, 49, 30, 180, 28, 28, 180, 70, 50, 163, 51, 28, 160, 88,
+            70, 220, 66, 35, 180, 40, 88, 200, 66, 59, 163, 60, 109, 180, 84, 76, 153, 80,
+            70, 168, 74, 66, 140, 41, 90, 158, 91, 84, 120, 63, 110, 140, 115, 84, 100, 80,
+            63, 160, 123, 99, 158, 100, 80, 140, 123, 123, 140, 120, 101, 120, 140, 140, 130, 140,
+        ];
+        assert_eq!(run(&FIXTURE, WIDTH, HEIGHT, DemosaicMethod::MalvarHeCutler), expected);
+    }
+
+    // 32x24 frame in the raw recording format, generated from a noisy scene with two cubes on a table.
+    // The reference outputs were made with the original per-pixel implementation.
+    #[test]
+    fn matches_reference_on_recorded_frame() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata/cubes.pixyraw");
+        let raw = RawReplaySource::open(&path, false).unwrap().next_raw().unwrap().unwrap();
+        let references: [(DemosaicMethod, &[u8]); 2] = [
+            (DemosaicMethod::Bilinear, include_bytes!("../testdata/cubes.bilinear.bgr")),
+            (DemosaicMethod::MalvarHeCutler, include_bytes!("../testdata/cubes.malvar.bgr")),
+        ];
+        for (method, expected) in references {
+            assert_eq!(run(&raw.bayer, raw.width, raw.height, method), expected, "{method:?}");
+        }
+    }
+
+    // The border is wider than half of these images, the mirroring must not mix up the colors
+    #[test]
+    fn small_images_keep_the_pattern() {
+        let bayer = [10, 20, 30, 40];
+        let expected = [10, 25, 40, 10, 20, 40, 10, 30, 40, 10, 25, 40];
+        assert_eq!(run(&bayer, 2, 2, DemosaicMethod::Bilinear), expected);
+
+        for method in [DemosaicMethod::Bilinear, DemosaicMethod::MalvarHeCutler] {
+            for (width, height) in [(2, 2), (2, 4), (4, 2), (4, 4)] {
+                // Flat color with B = 50, G = 120 and R = 200
+                let bayer: Vec<u8> = (0..width * height)
+                    .map(|i| match ((i / width) % 2, (i % width) % 2) {
+                        (0, 0) => 50,
+                        (1, 1) => 200,
+                        _ => 120,
+                    })
+                    .collect();
+                let bgr = run(&bayer, width, height, method);
+                assert!(bgr.chunks_exact(3).all(|p| p == [50, 120, 200]), "{method:?} {width}x{height}: {bgr:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_odd_sizes() {
+        assert!(demosaic_into(&[0; 6], 3, 2, DemosaicMethod::Bilinear, &mut [0; 18]).is_err());
+        assert!(demosaic_into(&[0; 4], 2, 2, DemosaicMethod::Bilinear, &mut [0; 6]).is_err());
+    }
+}
@@ -0,0 +1,111 @@
+use std::fmt;
+
+// Result codes from libpixyusb2 (PIXY_RESULT_* in Pixy2CCC.h)
+const PIXY_RESULT_ERROR: i32 = -1;
+const PIXY_RESULT_BUSY: i32 = -2;
+const PIXY_RESULT_CHECKSUM_ERROR: i32 = -3;
+const PIXY_RESULT_TIMEOUT: i32 = -4;
+const PIXY_RESULT_BUTTON_OVERRIDE: i32 = -5;
+const PIXY_RESULT_PROG_CHANGING: i32 = -6;
+// Returned by the bridge when the camera sent a frame of a different size than requested
+pub(crate) const BRIDGE_RESULT_FRAME_SIZE: i32 = -100;
+// Returned by the bridge when fewer pixels arrived than the frame has
+pub(crate) const BRIDGE_RESULT_SHORT_FRAME: i32 = -101;
+
+// Row count of a zero filled band at the bottom of a frame that counts as a cut off transfer
+const TRUNCATED_ROWS: usize = 8;
+// Mean level the rest of the frame needs for a zero band to count as cut off, darker scenes can have black rows
+const MIN_TRUNCATED_LEVEL: u64 = 24;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PixyError {
+    // General failure, also returned once the USB connection is gone
+    Failed,
+    // No new data yet, e.g. blocks requested faster than the camera frame rate
+    Busy,
+    ChecksumError,
+    Timeout,
+    // The user is holding the camera button to teach a signature
+    ButtonOverride,
+    ProgramChanging,
+    FrameSize,
+    // Part of the frame was lost in the transfer
+    ShortFrame,
+    NullFrame,
+    CorruptFrame(String),
+    Other(i32),
+}
+
+impl PixyError {
+    pub fn from_code(code: i32) -> PixyError {
+        match code {
+            PIXY_RESULT_ERROR => PixyError::Failed,
+            PIXY_RESULT_BUSY => PixyError::Busy,
+            PIXY_RESULT_CHECKSUM_ERROR => PixyError::ChecksumError,
+            PIXY_RESULT_TIMEOUT => PixyError::Timeout,
+            PIXY_RESULT_BUTTON_OVERRIDE => PixyError::ButtonOverride,
+            PIXY_RESULT_PROG_CHANGING => PixyError::ProgramChanging,
+            BRIDGE_RESULT_FRAME_SIZE => PixyError::FrameSize,
+            BRIDGE_RESULT_SHORT_FRAME => PixyError::ShortFrame,
+            code => PixyError::Other(code),
+        }
+    }
+
+    // Negative results become errors, others are passed on (e.g. the block count)
+    pub fn check(code: i32) -> Result<i32, PixyError> {
+        if code < 0 {
+            return Err(PixyError::from_code(code));
+        }
+        Ok(code)
+    }
+
+    // The library doesn't report disconnects separately, USB failures surface as general errors,
+    // timeouts or libusb codes
+    pub fn needs_reconnect(&self) -> bool {
+        matches!(self, PixyError::Failed | PixyError::Timeout | PixyError::Other(_))
+    }
+}
+
+impl fmt::Display for PixyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PixyError::Failed => write!(f, "Pixy camera request failed"),
+            PixyError::Busy => write!(f, "Pixy camera is busy"),
+            PixyError::ChecksumError => write!(f, "Pixy camera data has a wrong checksum"),
+            PixyError::Timeout => write!(f, "Pixy camera did not respond in time"),
+            PixyError::ButtonOverride => write!(f, "Pixy camera button is pressed"),
+            PixyError::ProgramChanging => write!(f, "Pixy camera program is changing"),
+            PixyError::FrameSize => write!(f, "Pixy camera sent a frame of the wrong size"),
+            PixyError::ShortFrame => write!(f, "Pixy camera sent only part of a frame"),
+            PixyError::NullFrame => write!(f, "Pixy camera returned no frame buffer"),
+            PixyError::CorruptFrame(reason) => write!(f, "Pixy camera frame is corrupt, {reason}"),
+            PixyError::Other(code) => write!(f, "Pixy camera error {code}"),
+        }
+    }
+}
+
+impl std::error::Error for PixyError {}
+
+// Catches the ways a USB transfer breaks a frame. A frame of a single value is rarely a real image,
+// the sensor noise alone varies it, and a cut off transfer leaves the end of the buffer zeroed.
+// Both can also come from a covered lens or a saturated sensor, so this is only used when enabled.
+// Transfers the bridge can see are short are always rejected as PixyError::ShortFrame.
+pub fn check_frame(bayer: &[u8], width: usize) -> Result<(), PixyError> {
+    let Some(first) = bayer.first() else {
+        return Err(PixyError::CorruptFrame("it is empty".to_string()));
+    };
+    if bayer.iter().all(|v| v == first) {
+        return Err(PixyError::CorruptFrame(format!("all pixels are {first}")));
+    }
+    // Frames too short for a band above the tail can't be told apart from a dark image
+    let tail = TRUNCATED_ROWS * width;
+    if bayer.len() <= 2 * tail {
+        return Ok(());
+    }
+    let (head, tail) = bayer.split_at(bayer.len() - tail);
+    let head_level = head.iter().map(|v| *v as u64).sum::<u64>() / head.len() as u64;
+    if head_level >= MIN_TRUNCATED_LEVEL && tail.iter().all(|v| *v == 0) {
+        return Err(PixyError::CorruptFrame(format!("the last {TRUNCATED_ROWS} rows are empty")));
+    }
+    Ok(())
+}
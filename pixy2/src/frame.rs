@@ -0,0 +1,63 @@
+use opencv::{core::{Mat, CV_8UC3}, imgproc::{cvt_color_def, COLOR_RGB2BGR}, prelude::*};
+
+use anyhow::bail;
+
+// Channel order of a 3 channel 8 bit image. OpenCV, and so the vision pipeline, expects BGR.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorOrder {
+    Rgb,
+    Bgr,
+}
+
+// Image that knows its channel order, so it can't be passed on with red and blue swapped
+#[derive(Debug, Clone)]
+pub struct Frame {
+    mat: Mat,
+    order: ColorOrder,
+}
+
+impl Frame {
+    pub fn new(mat: Mat, order: ColorOrder) -> anyhow::Result<Frame> {
+        if mat.typ() != CV_8UC3 {
+            bail!("Expected a 3 channel 8 bit image, got type {}", mat.typ());
+        }
+        Ok(Frame { mat, order })
+    }
+
+    pub fn order(&self) -> ColorOrder {
+        self.order
+    }
+
+    // Image in its own channel order, check `order` before using the colors
+    pub fn mat(&self) -> &Mat {
+        &self.mat
+    }
+
+    pub fn to_bgr(&self) -> anyhow::Result<Mat> {
+        match self.order {
+            ColorOrder::Bgr => Ok(self.mat.clone()),
+            ColorOrder::Rgb => convert(&self.mat, COLOR_RGB2BGR),
+        }
+    }
+
+    pub fn into_bgr(self) -> anyhow::Result<Mat> {
+        match self.order {
+            ColorOrder::Bgr => Ok(self.mat),
+            ColorOrder::Rgb => convert(&self.mat, COLOR_RGB2BGR),
+        }
+    }
+
+    pub fn to_rgb(&self) -> anyhow::Result<Mat> {
+        match self.order {
+            // The conversion just swaps the first and last channel, so it works both ways
+            ColorOrder::Bgr => convert(&self.mat, COLOR_RGB2BGR),
+            ColorOrder::Rgb => Ok(self.mat.clone()),
+        }
+    }
+}
+
+fn convert(mat: &Mat, code: i32) -> anyhow::Result<Mat> {
+    let mut converted = Mat::default();
+    cvt_color_def(mat, &mut converted, code)?;
+    Ok(converted)
+}
@@ -0,0 +1,172 @@
+use std::{fs, path::{Path, PathBuf}};
+
+use anyhow::{anyhow, bail};
+use opencv::{core::{Mat, Rect, Scalar, CV_8UC3}, imgcodecs::{imread, IMREAD_COLOR}, imgproc::{rectangle, FILLED, LINE_8}, prelude::*, videoio::{VideoCapture, CAP_ANY, CAP_PROP_POS_FRAMES}};
+
+use crate::{frame::{ColorOrder, Frame}, PixyCamera};
+
+// Anything that produces frames for the vision pipeline
+pub trait FrameSource {
+    // None once a finite source, e.g. a recording, has no more frames
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>>;
+}
+
+impl<S: FrameSource + ?Sized> FrameSource for Box<S> {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        (**self).next_frame()
+    }
+}
+
+impl FrameSource for PixyCamera {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        Ok(Some(self.get_frame()?))
+    }
+}
+
+// Reads the PNG files of a directory in file name order
+pub struct ImageDirectorySource {
+    paths: Vec<PathBuf>,
+    next: usize,
+    looping: bool,
+}
+
+impl ImageDirectorySource {
+    pub fn open(dir: &Path, looping: bool) -> anyhow::Result<ImageDirectorySource> {
+        let mut paths = fs::read_dir(dir)?
+            .map(|e| Ok(e?.path()))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        paths.retain(|p| p.extension().is_some_and(|e| e.eq_ignore_ascii_case("png")));
+        paths.sort();
+        if paths.is_empty() {
+            bail!("No PNG images in {}", dir.display());
+        }
+
+        Ok(ImageDirectorySource { paths, next: 0, looping })
+    }
+}
+
+impl FrameSource for ImageDirectorySource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        if self.next == self.paths.len() {
+            if !self.looping {
+                return Ok(None);
+            }
+            self.next = 0;
+        }
+        let path = &self.paths[self.next];
+        self.next += 1;
+
+        let frame = imread(path.to_str().ok_or(anyhow!("Invalid image path {}", path.display()))?, IMREAD_COLOR)?;
+        if frame.empty() {
+            bail!("Failed to read image {}", path.display());
+        }
+        // imread always decodes to BGR
+        Ok(Some(Frame::new(frame, ColorOrder::Bgr)?))
+    }
+}
+
+pub struct VideoSource {
+    capture: VideoCapture,
+    looping: bool,
+}
+
+impl VideoSource {
+    pub fn open(path: &Path, looping: bool) -> anyhow::Result<VideoSource> {
+        let capture = VideoCapture::from_file(path.to_str().ok_or(anyhow!("Invalid video path {}", path.display()))?, CAP_ANY)?;
+        if !capture.is_opened()? {
+            bail!("Failed to open video {}", path.display());
+        }
+
+        Ok(VideoSource { capture, looping })
+    }
+}
+
+impl FrameSource for VideoSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let mut frame = Mat::default();
+        if self.capture.read(&mut frame)? && !frame.empty() {
+            return Ok(Some(Frame::new(frame, ColorOrder::Bgr)?));
+        }
+        if !self.looping {
+            return Ok(None);
+        }
+
+        self.capture.set(CAP_PROP_POS_FRAMES, 0.0)?;
+        if !self.capture.read(&mut frame)? || frame.empty() {
+            bail!("Video has no frames");
+        }
+        Ok(Some(Frame::new(frame, ColorOrder::Bgr)?))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntheticObject {
+    // BGR
+    pub color: Scalar,
+    pub size: (i32, i32),
+    pub position: (f64, f64),
+    // Pixels per frame
+    pub velocity: (f64, f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct SyntheticConfig {
+    pub width: i32,
+    pub height: i32,
+    pub background: Scalar,
+    pub objects: Vec<SyntheticObject>,
+}
+
+impl SyntheticConfig {
+    // Pixy2 sized frames with a red, green and blue cube on a grey table
+    pub fn new() -> SyntheticConfig {
+        let cube = |color, position, velocity| SyntheticObject { color, size: (24, 24), position, velocity };
+        SyntheticConfig {
+            width: 316,
+            height: 208,
+            background: Scalar::new(90.0, 90.0, 90.0, 0.0),
+            objects: vec![
+                cube(Scalar::new(0.0, 0.0, 200.0, 0.0), (40.0, 40.0), (2.0, 1.0)),
+                cube(Scalar::new(0.0, 180.0, 0.0, 0.0), (150.0, 120.0), (-1.5, 0.5)),
+                cube(Scalar::new(200.0, 0.0, 0.0, 0.0), (250.0, 60.0), (0.0, -1.0)),
+            ],
+        }
+    }
+}
+
+// Draws solid colored boxes moving across a plain background, bouncing off the edges
+pub struct SyntheticSource {
+    config: SyntheticConfig,
+}
+
+impl SyntheticSource {
+    pub fn new(config: SyntheticConfig) -> SyntheticSource {
+        SyntheticSource { config }
+    }
+}
+
+impl FrameSource for SyntheticSource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let (width, height) = (self.config.width, self.config.height);
+        let mut frame = Mat::new_rows_cols_with_default(height, width, CV_8UC3, self.config.background)?;
+
+        for object in &mut self.config.objects {
+            let (w, h) = object.size;
+            let rect = Rect::new(object.position.0 as i32, object.position.1 as i32, w, h);
+            rectangle(&mut frame, rect, object.color, FILLED, LINE_8, 0)?;
+
+            object.position.0 += object.velocity.0;
+            object.position.1 += object.velocity.1;
+            if object.position.0 < 0.0 || object.position.0 + w as f64 > width as f64 {
+                object.velocity.0 = -object.velocity.0;
+                object.position.0 = object.position.0.clamp(0.0, (width - w).max(0) as f64);
+            }
+            if object.position.1 < 0.0 || object.position.1 + h as f64 > height as f64 {
+                object.velocity.1 = -object.velocity.1;
+                object.position.1 = object.position.1.clamp(0.0, (height - h).max(0) as f64);
+            }
+        }
+
+        Ok(Some(Frame::new(frame, ColorOrder::Bgr)?))
+    }
+}
@@ -1,143 +1,298 @@
-use std::alloc::{alloc, Layout};
-use std::{ptr, slice};
+use std::slice;
 use std::ptr::null_mut;
+use std::path::Path;
+use std::thread::sleep;
+use std::time::{Duration, Instant};
 
-use anyhow::{bail, Ok};
-use opencv::core::{Mat, Scalar, Scalar_, VecN};
+use anyhow::{bail, Context};
+
+pub mod camera_settings;
+pub mod capture;
+pub mod ccc;
+pub mod demosaic;
+pub mod error;
+pub mod frame;
+pub mod frame_source;
+pub mod line;
+pub mod raw_recording;
+
+use camera_settings::CameraSettings;
+use ccc::{Block, SignatureMask};
+use demosaic::{demosaic, DemosaicMethod};
+use error::{check_frame, PixyError};
+use frame::{ColorOrder, Frame};
+use line::{Barcode, FeatureMask, Intersection, LineFeatures, Vector};
+use raw_recording::RawRecorder;
 
 const PIXY2_RAW_FRAME_WIDTH: usize = 316;
 const PIXY2_RAW_FRAME_HEIGHT: usize = 208;
 const PIXY2_BAYER_FRAME_BUFFER_SIZE: usize = PIXY2_RAW_FRAME_WIDTH * PIXY2_RAW_FRAME_HEIGHT;
 
-#[allow(unused_imports)]
-#[cxx::bridge]
-mod ffi {
-    unsafe extern "C++" {
-        include!("bridge.h");
+// Servo positions for set_servos
+pub const SERVO_MIN: u16 = 0;
+pub const SERVO_CENTER: u16 = 500;
+pub const SERVO_MAX: u16 = 1000;
+
+#[cfg(not(feature = "mock"))]
+mod bridge;
+#[cfg(not(feature = "mock"))]
+use bridge::ffi;
+#[cfg(feature = "mock")]
+mod mock;
+#[cfg(feature = "mock")]
+use mock as ffi;
+
+#[derive(Debug, Clone)]
+pub struct RecoveryConfig {
+    // Attempts after the first failure of a request, reconnects count as attempts
+    pub max_retries: u32,
+    // Reopen the USB connection on errors that look like a lost camera
+    pub reconnect: bool,
+    // Time for the camera to enumerate again after it was plugged back in
+    pub reconnect_delay: Duration,
+    // Also retry frames whose content looks like a broken USB transfer. Off by default, a covered lens or a
+    // blank scene can look the same. Frames with fewer pixels than requested are always retried.
+    pub check_frames: bool,
+}
+
+impl RecoveryConfig {
+    pub fn new() -> RecoveryConfig {
+        RecoveryConfig {
+            max_retries: 5,
+            reconnect: true,
+            reconnect_delay: Duration::from_secs(1),
+            check_frames: false,
+        }
+    }
+}
+
+impl Default for RecoveryConfig {
+    fn default() -> RecoveryConfig {
+        RecoveryConfig::new()
+    }
+}
+
+// Camera programs whose results can be requested
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Program {
+    ColorConnectedComponents,
+    LineTracking,
+}
 
-        fn init() -> i32;
-        fn set_lamp(upper: i32, lower: i32);
-        fn stop() -> i32;
-        unsafe fn get_raw_frame(bayer_frame: *mut *mut u8) -> i32;
+impl Program {
+    fn name(&self) -> &'static str {
+        match self {
+            Program::ColorConnectedComponents => "color_connected_components",
+            Program::LineTracking => "line",
+        }
     }
 }
 
-pub struct PixyCamera {}
+pub struct PixyCamera {
+    demosaic_method: DemosaicMethod,
+    // Raw frames need the camera program stopped, blocks and line features need their program running.
+    // None until a program was selected, the camera starts with the default program set in PixyMon.
+    program: Option<Program>,
+    program_running: bool,
+    settings: CameraSettings,
+    recovery: RecoveryConfig,
+    recorder: Option<RawRecorder>,
+}
 
 impl PixyCamera {
     pub fn init() -> anyhow::Result<Self> {
-        if ffi::init() != 0 {
-            bail!("Failed to initialize pixy camera");
+        PixyError::check(ffi::init()).context("Failed to initialize pixy camera")?;
+        PixyError::check(ffi::stop()).context("Failed to stop pixy camera program")?;
+        Ok(PixyCamera {
+            demosaic_method: DemosaicMethod::MalvarHeCutler,
+            program: None,
+            program_running: false,
+            settings: CameraSettings::new(),
+            recovery: RecoveryConfig::new(),
+            recorder: None,
+        })
+    }
+
+    pub fn set_demosaic_method(&mut self, method: DemosaicMethod) {
+        self.demosaic_method = method;
+    }
+
+    pub fn set_recovery(&mut self, recovery: RecoveryConfig) {
+        self.recovery = recovery;
+    }
+
+    // Last settings applied, starts with the camera defaults
+    pub fn settings(&self) -> &CameraSettings {
+        &self.settings
+    }
+
+    pub fn set_lamp(&mut self, upper: bool, lower: bool) -> anyhow::Result<()> {
+        ffi::set_lamp(upper as i32, lower as i32);
+        self.settings.upper_lamp = upper;
+        self.settings.lower_lamp = lower;
+        self.record_settings()
+    }
+
+    // Writes every raw frame from now on to a recording at `path`, see raw_recording for the format
+    pub fn start_recording(&mut self, path: &Path) -> anyhow::Result<()> {
+        self.stop_recording()?;
+        self.recorder = Some(RawRecorder::create(path)?);
+        self.record_settings()
+    }
+
+    pub fn stop_recording(&mut self) -> anyhow::Result<()> {
+        if let Some(mut recorder) = self.recorder.take() {
+            recorder.flush()?;
         }
-        if ffi::stop() != 0 {
-            bail!("Failed to stop pixy camera program");
+        Ok(())
+    }
+
+    fn record_settings(&mut self) -> anyhow::Result<()> {
+        match &mut self.recorder {
+            Some(recorder) => recorder.write_settings(&self.settings),
+            None => Ok(()),
         }
-        Ok(PixyCamera {})
     }
 
-    pub fn get_frame(&self) -> anyhow::Result<Mat> {
-        let mut bayer_frame: *mut u8 = null_mut();
-        unsafe {
-            ffi::get_raw_frame(&mut bayer_frame);
+    pub fn apply_settings(&mut self, settings: &CameraSettings) -> anyhow::Result<()> {
+        settings.validate()?;
+        ffi::set_lamp(settings.upper_lamp as i32, settings.lower_lamp as i32);
+
+        let check = |result: i32, name: &str| PixyError::check(result).with_context(|| format!("Failed to set pixy camera {name}"));
+        check(ffi::set_camera_brightness(settings.brightness), "brightness")?;
+        check(ffi::set_auto_white_balance(settings.auto_white_balance), "auto white balance")?;
+        if let (false, Some(value)) = (settings.auto_white_balance, settings.white_balance) {
+            check(ffi::set_white_balance_value(value), "white balance")?;
         }
-        let bayer_frame = unsafe {
-            slice::from_raw_parts(bayer_frame, PIXY2_BAYER_FRAME_BUFFER_SIZE)
-        };
-        let rgb_frame = demosic(PIXY2_RAW_FRAME_WIDTH, PIXY2_RAW_FRAME_HEIGHT, bayer_frame);
-        let image_data = rgb_frame
-            .as_slice()
-            .iter()
-            .map(|v| VecN::<u8, 3>::from_array([((*v >> 16) & 0xff) as u8, ((*v >> 8) & 0xff) as u8, (*v & 0xFF) as u8]))
-            .collect::<Vec<_>>();
-        let mat = Mat::new_rows_cols_with_data(PIXY2_RAW_FRAME_HEIGHT as i32, PIXY2_RAW_FRAME_WIDTH as i32, image_data.as_slice())?.clone_pointee();
-        Ok(mat)
+        check(ffi::set_auto_exposure(settings.auto_exposure), "auto exposure")?;
+        if let (false, Some(value)) = (settings.auto_exposure, settings.exposure) {
+            check(ffi::set_exposure_value(value), "exposure")?;
+        }
+
+        self.settings = settings.clone();
+        self.record_settings()
     }
-}
 
-fn demosic(width: usize, height: usize, bayer_image: &[u8]) -> Vec<u32> {
-    let mut image = vec![0; bayer_image.len()];
+    fn stop_program(&mut self) -> Result<(), PixyError> {
+        if self.program_running {
+            PixyError::check(ffi::stop())?;
+            self.program_running = false;
+        }
+        Ok(())
+    }
 
-    // Gpt converted code, should probably be rewritten
-    for y in 0..height {
-        let mut yy = y;
-        if yy == 0 {
-            yy += 1;
-        } else if yy == height - 1 {
-            yy -= 1;
+    fn run_program(&mut self, program: Program) -> Result<(), PixyError> {
+        if self.program != Some(program) {
+            PixyError::check(ffi::change_prog(program.name()))?;
+            self.program = Some(program);
+            // Resume in case the program was stopped before the change
+            self.program_running = false;
+        }
+        if !self.program_running {
+            PixyError::check(ffi::resume())?;
+            self.program_running = true;
         }
-        let pixel0_offset = yy * width;
-        for x in 0..width {
-            let mut xx = x;
-            if xx == 0 {
-                xx += 1;
-            } else if xx == width - 1 {
-                xx -= 1;
+        Ok(())
+    }
+
+    // Reopens the connection and restores the program state and settings the camera lost
+    fn reconnect(&mut self) -> anyhow::Result<()> {
+        sleep(self.recovery.reconnect_delay);
+        PixyError::check(ffi::reinit()).context("Failed to reconnect to pixy camera")?;
+        PixyError::check(ffi::stop()).context("Failed to stop pixy camera program")?;
+        self.program = None;
+        self.program_running = false;
+        let settings = self.settings.clone();
+        self.apply_settings(&settings)
+    }
+
+    // Runs a request again after errors that can go away, reconnecting first if the camera seems lost
+    fn with_recovery<T>(&mut self, mut request: impl FnMut(&mut Self) -> Result<T, PixyError>) -> anyhow::Result<T> {
+        let mut attempt = 0;
+        loop {
+            let error = match request(self) {
+                Ok(value) => return Ok(value),
+                Err(e) => e,
+            };
+            attempt += 1;
+            if attempt > self.recovery.max_retries || matches!(error, PixyError::ButtonOverride | PixyError::FrameSize) {
+                return Err(error.into());
             }
-            let pixel_index = pixel0_offset + xx;
-
-            let (r, g, b) = if yy % 2 == 1 {
-                if xx % 2 == 1 {
-                    // Red pixel
-                    let r = bayer_image[pixel_index] as u32;
-                    let g = (
-                        bayer_image[pixel_index - 1] as u32
-                            + bayer_image[pixel_index + 1] as u32
-                            + bayer_image[pixel_index - width] as u32
-                            + bayer_image[pixel_index + width] as u32
-                    ) >> 2;
-                    let b = (
-                        bayer_image[pixel_index - width - 1] as u32
-                            + bayer_image[pixel_index - width + 1] as u32
-                            + bayer_image[pixel_index + width - 1] as u32
-                            + bayer_image[pixel_index + width + 1] as u32
-                    ) >> 2;
-                    (r, g, b)
-                } else {
-                    // Green pixel on red row
-                    let r = (
-                        bayer_image[pixel_index - 1] as u32 + bayer_image[pixel_index + 1] as u32
-                    ) >> 1;
-                    let g = bayer_image[pixel_index] as u32;
-                    let b = (
-                        bayer_image[pixel_index - width] as u32
-                            + bayer_image[pixel_index + width] as u32
-                    ) >> 1;
-                    (r, g, b)
-                }
-            } else {
-                if xx % 2 == 1 {
-                    // Green pixel on blue row
-                    let r = (
-                        bayer_image[pixel_index - width] as u32
-                            + bayer_image[pixel_index + width] as u32
-                    ) >> 1;
-                    let g = bayer_image[pixel_index] as u32;
-                    let b = (
-                        bayer_image[pixel_index - 1] as u32 + bayer_image[pixel_index + 1] as u32
-                    ) >> 1;
-                    (r, g, b)
-                } else {
-                    // Blue pixel
-                    let r = (
-                        bayer_image[pixel_index - width - 1] as u32
-                            + bayer_image[pixel_index - width + 1] as u32
-                            + bayer_image[pixel_index + width - 1] as u32
-                            + bayer_image[pixel_index + width + 1] as u32
-                    ) >> 2;
-                    let g = (
-                        bayer_image[pixel_index - 1] as u32
-                            + bayer_image[pixel_index + 1] as u32
-                            + bayer_image[pixel_index - width] as u32
-                            + bayer_image[pixel_index + width] as u32
-                    ) >> 2;
-                    let b = bayer_image[pixel_index] as u32;
-                    (r, g, b)
+            if !error.needs_reconnect() {
+                continue;
+            }
+            if !self.recovery.reconnect {
+                return Err(error.into());
+            }
+            while let Err(e) = self.reconnect() {
+                attempt += 1;
+                if attempt > self.recovery.max_retries {
+                    return Err(e.context(format!("Pixy camera lost ({error})")));
                 }
-            };
+            }
+        }
+    }
+
+    // Objects detected by the color connected components program, waits for the next camera frame
+    pub fn get_blocks(&mut self, signatures: SignatureMask, max_blocks: u8) -> anyhow::Result<Vec<Block>> {
+        let blocks = self.with_recovery(|camera| {
+            camera.run_program(Program::ColorConnectedComponents)?;
+            let mut blocks = Vec::new();
+            PixyError::check(ffi::get_blocks(true, signatures.0, max_blocks, &mut blocks))?;
+            Ok(blocks)
+        })?;
+        Ok(blocks.iter().map(Block::from).collect())
+    }
+
+    // Lines, intersections and barcodes seen by the line tracking program, waits for the next camera frame.
+    // With `main_only` just the vector the camera would follow is reported, instead of every feature in view.
+    pub fn get_line_features(&mut self, features: FeatureMask, main_only: bool) -> anyhow::Result<LineFeatures> {
+        let (mut vectors, mut intersections, mut barcodes) = (Vec::new(), Vec::new(), Vec::new());
+        self.with_recovery(|camera| {
+            camera.run_program(Program::LineTracking)?;
+            PixyError::check(ffi::get_line_features(main_only, features.0, true, &mut vectors, &mut intersections, &mut barcodes))
+        })?;
+        Ok(LineFeatures {
+            vectors: vectors.iter().map(Vector::from).collect(),
+            intersections: intersections.iter().map(Intersection::from).collect(),
+            barcodes: barcodes.iter().map(Barcode::from).collect(),
+        })
+    }
 
-            image[x + width * y] = (r << 16) | (g << 8) | b;
+    // Positions of the pan and tilt servos on the RC servo ports, SERVO_MIN to SERVO_MAX
+    pub fn set_servos(&mut self, pan: u16, tilt: u16) -> anyhow::Result<()> {
+        if pan > SERVO_MAX || tilt > SERVO_MAX {
+            bail!("Servo positions ({pan}, {tilt}) are outside of [{SERVO_MIN}, {SERVO_MAX}]");
         }
+        self.with_recovery(|_| PixyError::check(ffi::set_servos(pan, tilt)))?;
+        Ok(())
     }
 
-    image
-}
\ No newline at end of file
+    pub fn get_frame(&mut self) -> anyhow::Result<Frame> {
+        let (mode, window) = (self.settings.resolution.mode(), self.settings.window);
+        let (bayer_frame, time) = self.with_recovery(|camera| {
+            camera.stop_program()?;
+            let time = Instant::now();
+            let mut bayer_frame: *mut u8 = null_mut();
+            PixyError::check(unsafe {
+                ffi::get_raw_frame_window(mode, window.x, window.y, window.width, window.height, &mut bayer_frame)
+            })?;
+            if bayer_frame.is_null() {
+                return Err(PixyError::NullFrame);
+            }
+            // The buffer belongs to the library and stays valid until the next request
+            if camera.recovery.check_frames {
+                check_frame(unsafe { slice::from_raw_parts(bayer_frame, window.pixels()) }, window.width as usize)?;
+            }
+            Ok((bayer_frame, time))
+        })?;
+        let bayer_frame = unsafe {
+            slice::from_raw_parts(bayer_frame, window.pixels())
+        };
+        if let Some(recorder) = &mut self.recorder {
+            recorder.write_frame(time, bayer_frame, window.width as usize, window.height as usize)?;
+        }
+        let mat = demosaic(bayer_frame, window.width as usize, window.height as usize, self.demosaic_method)?;
+        Frame::new(mat, ColorOrder::Bgr)
+    }
+}
@@ -0,0 +1,108 @@
+use crate::ffi::{LineBarcode, LineIntersection, LineVector};
+
+// Line tracking works on a coarser grid than the video frames
+pub const LINE_GRID_WIDTH: u8 = 79;
+pub const LINE_GRID_HEIGHT: u8 = 52;
+
+const LINE_FLAG_INVALID: u8 = 0x02;
+const LINE_FLAG_INTERSECTION_PRESENT: u8 = 0x04;
+
+// Line segment pointing from the tail (x0, y0) to the head (x1, y1), in grid cells
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Vector {
+    pub x0: u8,
+    pub y0: u8,
+    pub x1: u8,
+    pub y1: u8,
+    // Stays the same while the camera tracks the same line
+    pub tracking_index: u8,
+    pub flags: u8,
+}
+
+impl Vector {
+    // Invalid vectors are still being filtered by the camera and may be noise
+    pub fn is_valid(&self) -> bool {
+        self.flags & LINE_FLAG_INVALID == 0
+    }
+
+    pub fn intersection_present(&self) -> bool {
+        self.flags & LINE_FLAG_INTERSECTION_PRESENT != 0
+    }
+
+    // Direction from tail to head (deg), 0 is to the right and 90 is up in the image
+    pub fn angle(&self) -> f64 {
+        (self.y0 as f64 - self.y1 as f64).atan2(self.x1 as f64 - self.x0 as f64).to_degrees()
+    }
+}
+
+impl From<&LineVector> for Vector {
+    fn from(v: &LineVector) -> Vector {
+        Vector { x0: v.x0, y0: v.y0, x1: v.x1, y1: v.y1, tracking_index: v.index, flags: v.flags }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IntersectionLine {
+    // Tracking index of the vector the line belongs to
+    pub tracking_index: u8,
+    // deg
+    pub angle: i16,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Intersection {
+    pub x: u8,
+    pub y: u8,
+    pub lines: Vec<IntersectionLine>,
+}
+
+impl From<&LineIntersection> for Intersection {
+    fn from(i: &LineIntersection) -> Intersection {
+        Intersection {
+            x: i.x,
+            y: i.y,
+            lines: i.lines.iter().map(|l| IntersectionLine { tracking_index: l.index, angle: l.angle }).collect(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Barcode {
+    pub x: u8,
+    pub y: u8,
+    pub flags: u8,
+    // 0-15
+    pub code: u8,
+}
+
+impl From<&LineBarcode> for Barcode {
+    fn from(b: &LineBarcode) -> Barcode {
+        Barcode { x: b.x, y: b.y, flags: b.flags, code: b.code }
+    }
+}
+
+// Features reported by the line tracking program, empty for features that weren't requested or seen
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineFeatures {
+    pub vectors: Vec<Vector>,
+    pub intersections: Vec<Intersection>,
+    pub barcodes: Vec<Barcode>,
+}
+
+// Set of feature types to request
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FeatureMask(pub u8);
+
+impl FeatureMask {
+    pub const VECTORS: FeatureMask = FeatureMask(0x01);
+    pub const INTERSECTIONS: FeatureMask = FeatureMask(0x02);
+    pub const BARCODES: FeatureMask = FeatureMask(0x04);
+
+    pub fn all() -> FeatureMask {
+        FeatureMask(0x07)
+    }
+
+    pub fn with(self, other: FeatureMask) -> FeatureMask {
+        FeatureMask(self.0 | other.0)
+    }
+}
@@ -0,0 +1,189 @@
+// Stand-in for the libpixyusb2 bridge with the same functions, serving a generated Bayer frame
+// with a red, green and blue square moving across a grey background. The color connected components
+// program reports the squares as signatures 1, 2 and 3. Line tracking sees a line drifting sideways
+// with a crossing line and a barcode next to it.
+
+use std::sync::{
+    atomic::{AtomicU32, Ordering},
+    Mutex,
+};
+
+use crate::{PIXY2_BAYER_FRAME_BUFFER_SIZE, PIXY2_RAW_FRAME_HEIGHT, PIXY2_RAW_FRAME_WIDTH};
+
+static FRAME: Mutex<[u8; PIXY2_BAYER_FRAME_BUFFER_SIZE]> = Mutex::new([0; PIXY2_BAYER_FRAME_BUFFER_SIZE]);
+static FRAME_COUNT: AtomicU32 = AtomicU32::new(0);
+
+const SQUARE_SIZE: usize = 24;
+const BACKGROUND: [u8; 3] = [90, 90, 90];
+
+// Copy of Block from Pixy2CCC.h, x and y are the center of the block
+pub struct CccBlock {
+    pub signature: u16,
+    pub x: u16,
+    pub y: u16,
+    pub width: u16,
+    pub height: u16,
+    pub angle: i16,
+    pub index: u8,
+    pub age: u8,
+}
+
+pub struct LineVector {
+    pub x0: u8,
+    pub y0: u8,
+    pub x1: u8,
+    pub y1: u8,
+    pub index: u8,
+    pub flags: u8,
+}
+
+pub struct LineIntersectionLine {
+    pub index: u8,
+    pub angle: i16,
+}
+
+pub struct LineIntersection {
+    pub x: u8,
+    pub y: u8,
+    pub lines: Vec<LineIntersectionLine>,
+}
+
+pub struct LineBarcode {
+    pub x: u8,
+    pub y: u8,
+    pub flags: u8,
+    pub code: u8,
+}
+
+// Top left corner, RGB color and signature of each square
+fn squares(count: usize) -> [(usize, usize, [u8; 3], u16); 3] {
+    [
+        ((count * 2) % (PIXY2_RAW_FRAME_WIDTH - SQUARE_SIZE), 40, [200, 0, 0], 1),
+        (150, (count + 60) % (PIXY2_RAW_FRAME_HEIGHT - SQUARE_SIZE), [0, 180, 0], 2),
+        (250 - count % 200, 120, [0, 0, 200], 3),
+    ]
+}
+
+pub fn init() -> i32 {
+    0
+}
+
+pub fn reinit() -> i32 {
+    0
+}
+
+pub fn set_lamp(_upper: i32, _lower: i32) {}
+
+pub fn stop() -> i32 {
+    0
+}
+
+pub fn resume() -> i32 {
+    0
+}
+
+// Like the real bridge, the returned buffer stays owned by the library and is overwritten by the next call
+#[allow(dead_code)]
+pub unsafe fn get_raw_frame(bayer_frame: *mut *mut u8) -> i32 {
+    get_raw_frame_window(0x21, 0, 0, PIXY2_RAW_FRAME_WIDTH as u16, PIXY2_RAW_FRAME_HEIGHT as u16, bayer_frame)
+}
+
+// The scene is always drawn at the getRawFrame resolution, the mode is ignored
+pub unsafe fn get_raw_frame_window(_mode: u8, x: u16, y: u16, width: u16, height: u16, bayer_frame: *mut *mut u8) -> i32 {
+    let (x0, y0, width, height) = (x as usize, y as usize, width as usize, height as usize);
+    if width * height > PIXY2_BAYER_FRAME_BUFFER_SIZE {
+        return -1;
+    }
+    let squares = squares(FRAME_COUNT.fetch_add(1, Ordering::Relaxed) as usize);
+
+    let mut frame = FRAME.lock().unwrap_or_else(|e| e.into_inner());
+    for y in y0..y0 + height {
+        for x in x0..x0 + width {
+            let rgb = squares
+                .iter()
+                .find(|(sx, sy, _, _)| (*sx..sx + SQUARE_SIZE).contains(&x) && (*sy..sy + SQUARE_SIZE).contains(&y))
+                .map(|(_, _, c, _)| *c)
+                .unwrap_or(BACKGROUND);
+            // Red on odd rows and columns, blue on even rows and columns, green elsewhere
+            let channel = match (y % 2, x % 2) {
+                (1, 1) => 0,
+                (0, 0) => 2,
+                _ => 1,
+            };
+            frame[(y - y0) * width + x - x0] = rgb[channel];
+        }
+    }
+    *bayer_frame = frame.as_mut_ptr();
+
+    0
+}
+
+pub fn set_camera_brightness(_brightness: u8) -> i32 {
+    0
+}
+
+pub fn set_auto_white_balance(_enabled: bool) -> i32 {
+    0
+}
+
+pub fn set_white_balance_value(_value: u32) -> i32 {
+    0
+}
+
+pub fn set_auto_exposure(_enabled: bool) -> i32 {
+    0
+}
+
+pub fn set_exposure_value(_value: u32) -> i32 {
+    0
+}
+
+pub fn get_blocks(_wait: bool, sigmap: u8, max_blocks: u8, blocks: &mut Vec<CccBlock>) -> i32 {
+    let count = FRAME_COUNT.fetch_add(1, Ordering::Relaxed) as usize;
+    blocks.clear();
+    for (index, (x, y, _, signature)) in squares(count).into_iter().enumerate() {
+        if sigmap & (1 << (signature - 1)) == 0 || blocks.len() == max_blocks as usize {
+            continue;
+        }
+        blocks.push(CccBlock {
+            signature,
+            x: (x + SQUARE_SIZE / 2) as u16,
+            y: (y + SQUARE_SIZE / 2) as u16,
+            width: SQUARE_SIZE as u16,
+            height: SQUARE_SIZE as u16,
+            angle: 0,
+            index: index as u8,
+            age: count.min(255) as u8,
+        });
+    }
+
+    blocks.len() as i32
+}
+
+pub fn change_prog(_name: &str) -> i32 {
+    0
+}
+
+pub fn set_servos(_pan: u16, _tilt: u16) -> i32 {
+    0
+}
+
+pub fn get_line_features(_main: bool, features: u8, _wait: bool, vectors: &mut Vec<LineVector>, intersections: &mut Vec<LineIntersection>, barcodes: &mut Vec<LineBarcode>) -> i32 {
+    let x = 20 + (FRAME_COUNT.fetch_add(1, Ordering::Relaxed) % 40) as u8;
+    vectors.clear();
+    intersections.clear();
+    barcodes.clear();
+    if features & 0x01 != 0 {
+        vectors.push(LineVector { x0: x, y0: 51, x1: x + 10, y1: 0, index: 0, flags: 0x04 });
+        vectors.push(LineVector { x0: 0, y0: 20, x1: 78, y1: 20, index: 1, flags: 0x04 });
+    }
+    if features & 0x02 != 0 {
+        let lines = vec![LineIntersectionLine { index: 0, angle: 79 }, LineIntersectionLine { index: 1, angle: 0 }];
+        intersections.push(LineIntersection { x: x + 6, y: 20, lines });
+    }
+    if features & 0x04 != 0 {
+        barcodes.push(LineBarcode { x: x + 12, y: 40, flags: 0, code: 5 });
+    }
+
+    (features & 0x07) as i32
+}
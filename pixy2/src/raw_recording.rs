@@ -0,0 +1,204 @@
+use std::{fs::File, io::{BufReader, BufWriter, ErrorKind, Read, Seek, SeekFrom, Write}, path::Path, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use anyhow::{bail, Context};
+
+use crate::{camera_settings::{CameraSettings, FrameWindow, Resolution}, demosaic::{demosaic, DemosaicMethod}, frame::{ColorOrder, Frame}, frame_source::FrameSource, PIXY2_BAYER_FRAME_BUFFER_SIZE};
+
+// File layout, all numbers little endian:
+//   header:   magic "PIXYRAW1", recording start as unix time (ns, u64)
+//   records:  tag byte followed by the record
+//     'S' settings: lamps, brightness, auto white balance (u8 each), white balance (u8 set flag, u32),
+//         auto exposure (u8), exposure (u8 set flag, u32), resolution mode (u8), window x, y, width, height (u16 each).
+//         Applies to all following frames.
+//     'F' frame: time since the recording start (ns, u64), width (u16), height (u16), width * height Bayer bytes
+pub const RAW_RECORDING_EXTENSION: &str = "pixyraw";
+
+const MAGIC: &[u8; 8] = b"PIXYRAW1";
+const SETTINGS_TAG: u8 = b'S';
+const FRAME_TAG: u8 = b'F';
+
+// Raw sensor data as it came from the camera
+#[derive(Debug, Clone)]
+pub struct RawFrame {
+    // Time since the recording start
+    pub time: Duration,
+    pub settings: CameraSettings,
+    pub width: usize,
+    pub height: usize,
+    pub bayer: Vec<u8>,
+}
+
+pub struct RawRecorder {
+    writer: BufWriter<File>,
+    start: Instant,
+}
+
+impl RawRecorder {
+    pub fn create(path: &Path) -> anyhow::Result<RawRecorder> {
+        let mut writer = BufWriter::new(File::create(path).with_context(|| format!("Failed to create {}", path.display()))?);
+        writer.write_all(MAGIC)?;
+        writer.write_all(&(SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos() as u64).to_le_bytes())?;
+        Ok(RawRecorder { writer, start: Instant::now() })
+    }
+
+    pub fn write_settings(&mut self, settings: &CameraSettings) -> anyhow::Result<()> {
+        let optional = |value: Option<u32>| {
+            let mut bytes = [0; 5];
+            if let Some(value) = value {
+                bytes[0] = 1;
+                bytes[1..].copy_from_slice(&value.to_le_bytes());
+            }
+            bytes
+        };
+        let w = settings.window;
+        let mut record = vec![
+            SETTINGS_TAG,
+            settings.upper_lamp as u8,
+            settings.lower_lamp as u8,
+            settings.brightness,
+            settings.auto_white_balance as u8,
+        ];
+        record.extend(optional(settings.white_balance));
+        record.push(settings.auto_exposure as u8);
+        record.extend(optional(settings.exposure));
+        record.push(settings.resolution.mode());
+        for v in [w.x, w.y, w.width, w.height] {
+            record.extend(v.to_le_bytes());
+        }
+
+        self.writer.write_all(&record)?;
+        Ok(())
+    }
+
+    // `time` is when the frame was requested, frames from before the recording start are stamped 0
+    pub fn write_frame(&mut self, time: Instant, bayer: &[u8], width: usize, height: usize) -> anyhow::Result<()> {
+        if bayer.len() != width * height {
+            bail!("Bayer frame has {} bytes, expected {}", bayer.len(), width * height);
+        }
+        self.writer.write_all(&[FRAME_TAG])?;
+        self.writer.write_all(&(time.saturating_duration_since(self.start).as_nanos() as u64).to_le_bytes())?;
+        self.writer.write_all(&(width as u16).to_le_bytes())?;
+        self.writer.write_all(&(height as u16).to_le_bytes())?;
+        self.writer.write_all(bayer)?;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> anyhow::Result<()> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+// Reads a recording back, demosaicing the frames like PixyCamera does
+pub struct RawReplaySource {
+    reader: BufReader<File>,
+    // Unix time the recording started
+    pub start_time: SystemTime,
+    settings: CameraSettings,
+    demosaic_method: DemosaicMethod,
+    looping: bool,
+}
+
+impl RawReplaySource {
+    pub fn open(path: &Path, looping: bool) -> anyhow::Result<RawReplaySource> {
+        let mut reader = BufReader::new(File::open(path).with_context(|| format!("Failed to open {}", path.display()))?);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            bail!("{} is not a raw pixy recording", path.display());
+        }
+        let start_time = UNIX_EPOCH + Duration::from_nanos(read_u64(&mut reader)?);
+
+        Ok(RawReplaySource {
+            reader,
+            start_time,
+            settings: CameraSettings::new(),
+            demosaic_method: DemosaicMethod::MalvarHeCutler,
+            looping,
+        })
+    }
+
+    pub fn set_demosaic_method(&mut self, method: DemosaicMethod) {
+        self.demosaic_method = method;
+    }
+
+    // Next frame with the settings it was captured with, None at the end of the recording
+    pub fn next_raw(&mut self) -> anyhow::Result<Option<RawFrame>> {
+        loop {
+            let mut tag = [0];
+            match self.reader.read_exact(&mut tag) {
+                Ok(()) => {}
+                Err(e) if e.kind() == ErrorKind::UnexpectedEof => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            match tag[0] {
+                SETTINGS_TAG => self.settings = self.read_settings()?,
+                FRAME_TAG => return Ok(Some(self.read_frame()?)),
+                tag => bail!("Unknown record tag {tag:#04x} in raw pixy recording"),
+            }
+        }
+    }
+
+    fn read_settings(&mut self) -> anyhow::Result<CameraSettings> {
+        let mut record = [0; 24];
+        self.reader.read_exact(&mut record)?;
+        let optional = |bytes: &[u8]| (bytes[0] != 0).then(|| u32::from_le_bytes(bytes[1..5].try_into().unwrap()));
+        let u16_at = |i: usize| u16::from_le_bytes([record[i], record[i + 1]]);
+
+        Ok(CameraSettings {
+            upper_lamp: record[0] != 0,
+            lower_lamp: record[1] != 0,
+            brightness: record[2],
+            auto_white_balance: record[3] != 0,
+            white_balance: optional(&record[4..9]),
+            auto_exposure: record[9] != 0,
+            exposure: optional(&record[10..15]),
+            resolution: Resolution::from_mode(record[15]).context("Unknown resolution mode in raw pixy recording")?,
+            window: FrameWindow { x: u16_at(16), y: u16_at(18), width: u16_at(20), height: u16_at(22) },
+        })
+    }
+
+    fn read_frame(&mut self) -> anyhow::Result<RawFrame> {
+        let time = Duration::from_nanos(read_u64(&mut self.reader)?);
+        let mut size = [0; 4];
+        self.reader.read_exact(&mut size)?;
+        let (width, height) = (u16::from_le_bytes([size[0], size[1]]) as usize, u16::from_le_bytes([size[2], size[3]]) as usize);
+        if width * height > PIXY2_BAYER_FRAME_BUFFER_SIZE {
+            bail!("Frame of {width}x{height} in raw pixy recording is larger than the camera frame buffer");
+        }
+        let mut bayer = vec![0; width * height];
+        self.reader.read_exact(&mut bayer).context("Raw pixy recording ends in the middle of a frame")?;
+
+        Ok(RawFrame { time, settings: self.settings.clone(), width, height, bayer })
+    }
+
+    fn rewind(&mut self) -> anyhow::Result<()> {
+        self.reader.seek(SeekFrom::Start((MAGIC.len() + 8) as u64))?;
+        self.settings = CameraSettings::new();
+        Ok(())
+    }
+}
+
+impl FrameSource for RawReplaySource {
+    fn next_frame(&mut self) -> anyhow::Result<Option<Frame>> {
+        let raw = match self.next_raw()? {
+            Some(raw) => raw,
+            None if self.looping => {
+                self.rewind()?;
+                match self.next_raw()? {
+                    Some(raw) => raw,
+                    None => bail!("Raw pixy recording has no frames"),
+                }
+            }
+            None => return Ok(None),
+        };
+        let mat = demosaic(&raw.bayer, raw.width, raw.height, self.demosaic_method)?;
+        Ok(Some(Frame::new(mat, ColorOrder::Bgr)?))
+    }
+}
+
+fn read_u64(reader: &mut impl Read) -> anyhow::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,125 @@
+use std::time::{Duration, Instant};
+
+use crate::{dashboard::ErrorIds, Robot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecoveryPolicy {
+    // Only report alarms, the operator has to clear them
+    ReportOnly,
+    ClearError,
+    // Clear the error and enable the robot again, needed after alarms that disable the arm
+    ClearAndEnable,
+}
+
+#[derive(Debug, Clone)]
+pub struct AlarmConfig {
+    pub policy: RecoveryPolicy,
+    pub retry_interval: Duration,
+    pub max_attempts: u32,
+}
+
+impl AlarmConfig {
+    pub fn new() -> AlarmConfig {
+        AlarmConfig {
+            policy: RecoveryPolicy::ClearError,
+            retry_interval: Duration::from_secs(2),
+            max_attempts: 3,
+        }
+    }
+}
+
+impl Default for AlarmConfig {
+    fn default() -> AlarmConfig {
+        AlarmConfig::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlarmEvent {
+    Raised(ErrorIds),
+    RecoveryAttempted { attempt: u32 },
+    // Gave up recovering after the maximum number of attempts
+    RecoveryFailed { attempts: u32 },
+    Cleared,
+}
+
+// Watches feedback for controller alarms and recovers from them according to the policy
+pub struct AlarmSupervisor {
+    config: AlarmConfig,
+    active: Option<ErrorIds>,
+    attempts: u32,
+    last_attempt: Option<Instant>,
+    gave_up: bool,
+}
+
+impl AlarmSupervisor {
+    pub fn new(config: AlarmConfig) -> AlarmSupervisor {
+        AlarmSupervisor {
+            config,
+            active: None,
+            attempts: 0,
+            last_attempt: None,
+            gave_up: false,
+        }
+    }
+
+    pub fn active_alarm(&self) -> Option<&ErrorIds> {
+        self.active.as_ref()
+    }
+
+    // Should be called regularly, e.g. once per control loop iteration
+    pub fn update(&mut self, robot: &mut dyn Robot) -> Vec<AlarmEvent> {
+        let mut events = Vec::new();
+
+        if !robot.feedback().has_error() {
+            if self.active.take().is_some() {
+                events.push(AlarmEvent::Cleared);
+            }
+            self.attempts = 0;
+            self.last_attempt = None;
+            self.gave_up = false;
+            return events;
+        }
+
+        if self.active.is_none() {
+            let ids = robot.error_ids().unwrap_or_else(|e| {
+                log::error!("Failed to query error ids: {e}");
+                ErrorIds::default()
+            });
+            events.push(AlarmEvent::Raised(ids.clone()));
+            self.active = Some(ids);
+        }
+
+        if self.config.policy == RecoveryPolicy::ReportOnly {
+            return events;
+        }
+        if self.last_attempt.is_some_and(|t| t.elapsed() < self.config.retry_interval) {
+            return events;
+        }
+        if self.attempts >= self.config.max_attempts {
+            if !self.gave_up {
+                self.gave_up = true;
+                events.push(AlarmEvent::RecoveryFailed { attempts: self.attempts });
+            }
+            return events;
+        }
+
+        self.attempts += 1;
+        self.last_attempt = Some(Instant::now());
+        events.push(AlarmEvent::RecoveryAttempted { attempt: self.attempts });
+        if let Err(e) = self.recover(robot) {
+            log::error!("Alarm recovery attempt {} failed: {e}", self.attempts);
+        }
+
+        events
+    }
+
+    fn recover(&self, robot: &mut dyn Robot) -> anyhow::Result<()> {
+        robot.clear_error()?;
+        if self.config.policy == RecoveryPolicy::ClearAndEnable {
+            robot.enable()?;
+        }
+
+        Ok(())
+    }
+}
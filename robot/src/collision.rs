@@ -0,0 +1,201 @@
+use std::{fmt, sync::mpsc::{self, Receiver}, thread};
+
+use nalgebra::{Vector4, Vector6};
+
+use crate::{feedback_data::FeedbackData, feedback_hub::FeedbackSample, EmergencyHandle};
+
+#[derive(Debug, Clone)]
+pub struct CollisionConfig {
+    // Largest allowed |i_actual - i_target| per joint (A), infinite for unused joints
+    pub current_thresholds: [f64; 6],
+    // Largest allowed change of the TCP force norm between two frames (N)
+    pub force_spike_threshold: f64,
+    // Number of consecutive frames a limit must be exceeded before it is reported, filters out single noisy frames
+    pub debounce_frames: u32,
+    pub stop_on_collision: bool,
+}
+
+impl CollisionConfig {
+    pub fn new() -> CollisionConfig {
+        CollisionConfig {
+            current_thresholds: [1.5, 1.5, 1.5, 1.0, f64::INFINITY, f64::INFINITY],
+            force_spike_threshold: 20.0,
+            debounce_frames: 3,
+            // Only report until the thresholds have been tuned on logs from the real arm, the guesses above
+            // could stop it during normal moves
+            stop_on_collision: false,
+        }
+    }
+}
+
+impl Default for CollisionConfig {
+    fn default() -> CollisionConfig {
+        CollisionConfig::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Anomaly {
+    CurrentDeviation { joint: usize, deviation: f64, threshold: f64 },
+    ForceSpike { change: f64, threshold: f64 },
+}
+
+impl fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Anomaly::CurrentDeviation { joint, deviation, threshold } => write!(f, "Joint {} current deviates by {deviation:.2} A (limit {threshold} A)", joint + 1),
+            Anomaly::ForceSpike { change, threshold } => write!(f, "TCP force changed by {change:.1} N in one frame (limit {threshold} N)"),
+        }
+    }
+}
+
+// Flags collisions and unexpected loads from the joint currents and TCP force in feedback
+pub struct CollisionMonitor {
+    config: CollisionConfig,
+    current_over: [u32; 6],
+    prev_force: Option<f64>,
+}
+
+impl CollisionMonitor {
+    pub fn new(config: CollisionConfig) -> CollisionMonitor {
+        CollisionMonitor {
+            config,
+            current_over: [0; 6],
+            prev_force: None,
+        }
+    }
+
+    pub fn check(&mut self, feedback: &FeedbackData) -> Vec<Anomaly> {
+        let mut anomalies = Vec::new();
+
+        for joint in 0..6 {
+            let threshold = self.config.current_thresholds[joint];
+            let deviation = (feedback.i_actual[joint] - feedback.i_target[joint]).abs();
+            if deviation > threshold {
+                self.current_over[joint] += 1;
+                if self.current_over[joint] == self.config.debounce_frames.max(1) {
+                    anomalies.push(Anomaly::CurrentDeviation { joint, deviation, threshold });
+                }
+            } else {
+                self.current_over[joint] = 0;
+            }
+        }
+
+        let force = Vector6::from_column_slice(&feedback.tcp_force).xyz().norm();
+        if let Some(prev) = self.prev_force.replace(force) {
+            let change = (force - prev).abs();
+            let threshold = self.config.force_spike_threshold;
+            // A spike is a single large jump, so it is not debounced
+            if change > threshold {
+                anomalies.push(Anomaly::ForceSpike { change, threshold });
+            }
+        }
+
+        anomalies
+    }
+
+    // Checks every frame from the subscription on a separate thread, stopping the robot if configured
+    pub fn spawn(mut self, feedback: Receiver<FeedbackData>, mut stop: Option<Box<dyn EmergencyHandle>>) -> Receiver<Anomaly> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for frame in feedback {
+                let anomalies = self.check(&frame);
+                if anomalies.is_empty() {
+                    continue;
+                }
+                if self.config.stop_on_collision {
+                    if let Some(stop) = stop.as_mut() {
+                        log::warn!("Stopping robot: {}", anomalies[0]);
+                        if let Err(e) = stop.stop() {
+                            log::error!("Failed to stop robot after collision: {e}");
+                        }
+                    }
+                }
+                for anomaly in anomalies {
+                    if tx.send(anomaly).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        rx
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct GraspLoadConfig {
+    // Joints whose torque carries the weight of the payload (0 indexed)
+    pub gravity_joints: Vec<usize>,
+    // Smallest torque change (N·m) that counts as an object being held
+    pub torque_threshold: f64,
+    // The comparison is only meaningful close to the pose where the baseline was taken (mm)
+    pub pose_tolerance: f64,
+}
+
+impl GraspLoadConfig {
+    pub fn new() -> GraspLoadConfig {
+        GraspLoadConfig {
+            gravity_joints: vec![1, 2],
+            torque_threshold: 0.3,
+            pose_tolerance: 5.0,
+        }
+    }
+}
+
+impl Default for GraspLoadConfig {
+    fn default() -> GraspLoadConfig {
+        GraspLoadConfig::new()
+    }
+}
+
+// Detects a grasped object from the change in joint torque compared to the same pose without it
+#[derive(Debug, Clone)]
+pub struct GraspLoadCheck {
+    config: GraspLoadConfig,
+    // Pose and joint torques without the object
+    baselines: Vec<(Vector4<f64>, [f64; 6])>,
+}
+
+impl GraspLoadCheck {
+    // Should be taken while the arm is still and holding nothing
+    pub fn capture(config: GraspLoadConfig, feedback: &FeedbackData) -> GraspLoadCheck {
+        GraspLoadCheck {
+            config,
+            baselines: vec![(pose(feedback), feedback.m_actual)],
+        }
+    }
+
+    // Baselines from every pose the arm rested at in `samples`, e.g. the approach pose above an object it
+    // then moved down to. Samples taken while moving are skipped, their torques include acceleration.
+    pub fn capture_history(config: GraspLoadConfig, samples: &[FeedbackSample]) -> GraspLoadCheck {
+        GraspLoadCheck {
+            config,
+            baselines: samples
+                .iter()
+                .filter(|s| !s.feedback.is_running())
+                .map(|s| (s.pose(), s.feedback.m_actual))
+                .collect(),
+        }
+    }
+
+    // None if the arm is too far from every baseline pose to tell
+    pub fn object_present(&self, feedback: &FeedbackData) -> Option<bool> {
+        let current = pose(feedback);
+        let (_, baseline_torque) = self.baselines
+            .iter()
+            .map(|(p, torque)| ((current - p).xyz().norm(), torque))
+            .filter(|(distance, _)| *distance <= self.config.pose_tolerance)
+            .min_by(|a, b| a.0.total_cmp(&b.0))?;
+        let change: f64 = self.config.gravity_joints
+            .iter()
+            .map(|j| (feedback.m_actual[*j] - baseline_torque[*j]).abs())
+            .sum();
+
+        Some(change >= self.config.torque_threshold)
+    }
+}
+
+fn pose(feedback: &FeedbackData) -> Vector4<f64> {
+    let [x, y, z, r, ..] = feedback.tool_vector_actual;
+    Vector4::new(x, y, z, r)
+}
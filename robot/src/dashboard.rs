@@ -0,0 +1,98 @@
+use anyhow::{anyhow, bail};
+
+// Reply on the dashboard port, formatted as `ErrorID,{values},Function(params);`
+#[derive(Debug, Clone)]
+pub struct DashboardResponse {
+    pub error_id: i64,
+    pub values: String,
+    pub function: String,
+}
+
+impl DashboardResponse {
+    pub fn parse(line: &str) -> anyhow::Result<DashboardResponse> {
+        let line = line.trim();
+        let (error_id, rest) = line
+            .split_once(',')
+            .ok_or(anyhow!("Malformed dashboard response {line}"))?;
+        let values_start = rest.find('{').ok_or(anyhow!("Missing values in dashboard response {line}"))?;
+        let values_end = rest.rfind('}').ok_or(anyhow!("Missing values in dashboard response {line}"))?;
+        if values_start >= values_end {
+            bail!("Malformed values in dashboard response {line}");
+        }
+        let function = rest[values_end + 1..].trim_start_matches(',').trim_end_matches(';');
+
+        Ok(DashboardResponse {
+            error_id: error_id.trim().parse()?,
+            values: rest[values_start + 1..values_end].to_string(),
+            function: function.to_string(),
+        })
+    }
+
+    // Name of the command this is a reply to, without parameters
+    pub fn function_name(&self) -> &str {
+        self.function.split('(').next().unwrap_or("")
+    }
+
+    // Name of the command a raw reply is for, without parsing the rest of it
+    pub fn reply_function_name(line: &str) -> Option<&str> {
+        let (_, function) = line.trim().rsplit_once('}')?;
+        function.trim_start_matches(',').split('(').next()
+    }
+
+    pub fn ok(self) -> anyhow::Result<DashboardResponse> {
+        if self.error_id != 0 {
+            bail!("Dashboard command {} failed with error {}", self.function, self.error_id);
+        }
+        Ok(self)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ErrorIds {
+    pub controller: Vec<i64>,
+    // Servo alarms for each joint
+    pub servo: Vec<Vec<i64>>,
+}
+
+impl ErrorIds {
+    // Parses the values of a GetErrorID() reply, e.g. `[[22],[],[],[],[],[],[]]`
+    pub fn parse(values: &str) -> anyhow::Result<ErrorIds> {
+        let mut lists = Vec::new();
+        let mut current = Vec::new();
+        let mut number = String::new();
+        let mut depth = 0;
+
+        for c in values.chars() {
+            match c {
+                '[' => depth += 1,
+                ']' | ',' => {
+                    if !number.trim().is_empty() {
+                        current.push(number.trim().parse::<i64>()?);
+                    }
+                    number.clear();
+                    if c == ']' {
+                        // Ids directly in the outer list are treated as controller errors
+                        if depth == 2 || (depth == 1 && lists.is_empty() && !current.is_empty()) {
+                            lists.push(std::mem::take(&mut current));
+                        }
+                        depth -= 1;
+                    }
+                }
+                _ => number.push(c),
+            }
+        }
+        if depth != 0 {
+            bail!("Malformed error id list {values}");
+        }
+
+        let mut lists = lists.into_iter();
+        Ok(ErrorIds {
+            controller: lists.next().unwrap_or_default(),
+            servo: lists.collect(),
+        })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.controller.is_empty() && self.servo.iter().all(|s| s.is_empty())
+    }
+}
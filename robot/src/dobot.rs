@@ -0,0 +1,126 @@
+use std::{thread::{self, sleep}, time::Duration};
+
+use nalgebra::Vector4;
+
+use crate::{dashboard::ErrorIds, feedback_data::FeedbackData, feedback_layout::LayoutSelection, motion_queue::MotionParams, feedback_hub::{FeedbackHub, FeedbackSample, DEFAULT_HISTORY_CAPACITY}, safety::SafetyEnvelope, EmergencyHandle, Robot, RobotConn, RobotFeedbackConn};
+
+// Dobot arm controlled over the TCP dashboard, motion and feedback ports
+pub struct DobotRobot {
+    conn: RobotConn,
+    feedback: FeedbackHub,
+}
+
+impl DobotRobot {
+    pub fn connect() -> anyhow::Result<DobotRobot> {
+        DobotRobot::connect_with_layout(LayoutSelection::Auto)
+    }
+
+    // Use a fixed layout for firmware that can't be told apart from the feedback
+    pub fn connect_with_layout(layouts: LayoutSelection) -> anyhow::Result<DobotRobot> {
+        let conn = RobotConn::connect()?;
+        let mut feedback_conn = RobotFeedbackConn::connect_with_layout(layouts)?;
+        let feedback = FeedbackHub::new(DEFAULT_HISTORY_CAPACITY);
+        feedback.publish(feedback_conn.receive_feedback()?);
+
+        {
+            let feedback = feedback.clone();
+            thread::spawn(move || {
+                run_feedback_loop(feedback_conn, feedback);
+            });
+        }
+
+        Ok(DobotRobot { conn, feedback })
+    }
+
+    pub fn set_safety_envelope(&mut self, envelope: Option<SafetyEnvelope>) {
+        self.conn.set_safety_envelope(envelope);
+    }
+
+    pub fn conn(&mut self) -> &mut RobotConn {
+        &mut self.conn
+    }
+
+    fn latest_sample(&self) -> FeedbackSample {
+        // The first frame is published before the robot is returned from connect
+        self.feedback.latest().expect("Feedback should have been received on connect")
+    }
+}
+
+impl Robot for DobotRobot {
+    fn enable(&mut self) -> anyhow::Result<()> {
+        self.conn.enable_robot()
+    }
+
+    fn disable(&mut self) -> anyhow::Result<()> {
+        self.conn.disable_robot()
+    }
+
+    fn move_to_with(&mut self, target: &Vector4<f64>, params: &MotionParams) -> anyhow::Result<()> {
+        self.conn.mov_j_with(target.x, target.y, target.z, target.w, params)
+    }
+
+    fn move_relative(&mut self, delta: &Vector4<f64>) -> anyhow::Result<()> {
+        let current = self.pose();
+        self.conn.mov_j_relative(&current, delta)
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.conn.stop()
+    }
+
+    fn pause(&mut self) -> anyhow::Result<()> {
+        self.conn.pause()
+    }
+
+    fn resume(&mut self) -> anyhow::Result<()> {
+        self.conn.resume()
+    }
+
+    fn start_drag(&mut self) -> anyhow::Result<()> {
+        self.conn.start_drag()
+    }
+
+    fn stop_drag(&mut self) -> anyhow::Result<()> {
+        self.conn.stop_drag()
+    }
+
+    fn emergency_handle(&self) -> anyhow::Result<Box<dyn EmergencyHandle>> {
+        Ok(Box::new(self.conn.dashboard_handle()))
+    }
+
+    fn set_digital_output(&mut self, index: u32, status: bool) -> anyhow::Result<()> {
+        self.conn.set_do(index as i32, status)
+    }
+
+    fn pose(&self) -> Vector4<f64> {
+        self.latest_sample().pose()
+    }
+
+    fn feedback(&self) -> FeedbackData {
+        self.latest_sample().feedback
+    }
+
+    fn feedback_hub(&self) -> FeedbackHub {
+        self.feedback.clone()
+    }
+
+    fn error_ids(&mut self) -> anyhow::Result<ErrorIds> {
+        self.conn.get_error_ids()
+    }
+
+    fn clear_error(&mut self) -> anyhow::Result<()> {
+        self.conn.clear_error()
+    }
+}
+
+fn run_feedback_loop(mut robot_feedback_conn: RobotFeedbackConn, feedback: FeedbackHub) {
+    loop {
+        match robot_feedback_conn.receive_feedback() {
+            Ok(res) => feedback.publish(res),
+            Err(e) => {
+                log::error!("Error receiving feedback {e:?}");
+                sleep(Duration::from_secs(1));
+            }
+        }
+    }
+}
@@ -0,0 +1,231 @@
+use std::{thread::sleep, time::{Duration, Instant}};
+
+use nalgebra::Vector4;
+
+use crate::{collision::{GraspLoadCheck, GraspLoadConfig}, feedback_data::FeedbackData, Robot};
+
+// How far back the feedback history is searched for load baselines when grasping
+const LOAD_BASELINE_WINDOW: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EndEffectorState {
+    Released,
+    // Actuated, but there is no way to verify that something was grasped, or the arm is too far
+    // from where the load baseline was taken
+    Engaged,
+    // Actuated and the verification confirms an object
+    Holding,
+    // Actuated but the verification found nothing
+    Empty,
+}
+
+impl EndEffectorState {
+    pub fn is_actuated(&self) -> bool {
+        *self != EndEffectorState::Released
+    }
+}
+
+// How to check that a grasp succeeded, all configured checks must pass
+#[derive(Debug, Clone)]
+pub struct GraspVerification {
+    // Digital input that is high while an object is held (vacuum switch, part sensor)
+    pub input: Option<u32>,
+    // Change in joint torque compared to the poses the arm rested at before the grasp
+    pub load: Option<GraspLoadConfig>,
+}
+
+impl GraspVerification {
+    pub fn none() -> GraspVerification {
+        GraspVerification { input: None, load: None }
+    }
+
+    // Load baseline from the feedback before the object is grasped
+    fn capture_load(&self, robot: &dyn Robot) -> Option<GraspLoadCheck> {
+        let config = self.load.clone()?;
+        let now = Instant::now();
+        let samples = robot.feedback_hub().history_between(now.checked_sub(LOAD_BASELINE_WINDOW).unwrap_or(now), now);
+        Some(GraspLoadCheck::capture_history(config, &samples))
+    }
+}
+
+pub trait EndEffector: Send {
+    // Offset from the hand pose to where the tool should be when grasping
+    fn grasp_offset(&self) -> Vector4<f64>;
+    fn grasp(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()>;
+    fn release(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()>;
+    fn state(&self, feedback: &FeedbackData) -> EndEffectorState;
+    // Output states to apply when the robot is stopped by a watchdog
+    fn safe_outputs(&self) -> Vec<(u32, bool)>;
+}
+
+#[derive(Debug, Clone)]
+pub struct SuctionCupConfig {
+    pub vacuum_output: u32,
+    // Optional output that blows air to drop the object faster
+    pub blow_off_output: Option<u32>,
+    pub actuation_delay: Duration,
+    pub grasp_offset: Vector4<f64>,
+    pub verification: GraspVerification,
+}
+
+impl SuctionCupConfig {
+    pub fn new() -> SuctionCupConfig {
+        SuctionCupConfig {
+            vacuum_output: 3,
+            blow_off_output: None,
+            actuation_delay: Duration::from_secs(1),
+            grasp_offset: Vector4::new(0.0, 0.0, -137.0, 0.0),
+            verification: GraspVerification::none(),
+        }
+    }
+}
+
+impl Default for SuctionCupConfig {
+    fn default() -> SuctionCupConfig {
+        SuctionCupConfig::new()
+    }
+}
+
+pub struct SuctionCup {
+    config: SuctionCupConfig,
+    load_check: Option<GraspLoadCheck>,
+}
+
+impl SuctionCup {
+    pub fn new(config: SuctionCupConfig) -> SuctionCup {
+        SuctionCup { config, load_check: None }
+    }
+}
+
+impl EndEffector for SuctionCup {
+    fn grasp_offset(&self) -> Vector4<f64> {
+        self.config.grasp_offset
+    }
+
+    fn grasp(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()> {
+        self.load_check = self.config.verification.capture_load(robot);
+        if let Some(blow_off) = self.config.blow_off_output {
+            robot.set_digital_output(blow_off, false)?;
+        }
+        robot.set_digital_output(self.config.vacuum_output, true)?;
+        sleep(self.config.actuation_delay);
+
+        Ok(())
+    }
+
+    fn release(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()> {
+        self.load_check = None;
+        robot.set_digital_output(self.config.vacuum_output, false)?;
+        if let Some(blow_off) = self.config.blow_off_output {
+            robot.set_digital_output(blow_off, true)?;
+            sleep(self.config.actuation_delay);
+            robot.set_digital_output(blow_off, false)?;
+        }
+
+        Ok(())
+    }
+
+    fn state(&self, feedback: &FeedbackData) -> EndEffectorState {
+        actuated_state(feedback.digital_output(self.config.vacuum_output), &self.config.verification, self.load_check.as_ref(), feedback)
+    }
+
+    fn safe_outputs(&self) -> Vec<(u32, bool)> {
+        let mut outputs = vec![(self.config.vacuum_output, false)];
+        outputs.extend(self.config.blow_off_output.map(|o| (o, false)));
+        outputs
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ParallelGripperConfig {
+    pub close_output: u32,
+    // Grippers driven by a double acting valve need a separate output for opening
+    pub open_output: Option<u32>,
+    pub actuation_delay: Duration,
+    pub grasp_offset: Vector4<f64>,
+    pub verification: GraspVerification,
+}
+
+impl ParallelGripperConfig {
+    pub fn new() -> ParallelGripperConfig {
+        ParallelGripperConfig {
+            close_output: 1,
+            open_output: Some(2),
+            actuation_delay: Duration::from_millis(500),
+            grasp_offset: Vector4::new(0.0, 0.0, -137.0, 0.0),
+            verification: GraspVerification::none(),
+        }
+    }
+}
+
+impl Default for ParallelGripperConfig {
+    fn default() -> ParallelGripperConfig {
+        ParallelGripperConfig::new()
+    }
+}
+
+pub struct ParallelGripper {
+    config: ParallelGripperConfig,
+    load_check: Option<GraspLoadCheck>,
+}
+
+impl ParallelGripper {
+    pub fn new(config: ParallelGripperConfig) -> ParallelGripper {
+        ParallelGripper { config, load_check: None }
+    }
+}
+
+impl EndEffector for ParallelGripper {
+    fn grasp_offset(&self) -> Vector4<f64> {
+        self.config.grasp_offset
+    }
+
+    fn grasp(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()> {
+        self.load_check = self.config.verification.capture_load(robot);
+        if let Some(open) = self.config.open_output {
+            robot.set_digital_output(open, false)?;
+        }
+        robot.set_digital_output(self.config.close_output, true)?;
+        sleep(self.config.actuation_delay);
+
+        Ok(())
+    }
+
+    fn release(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()> {
+        self.load_check = None;
+        robot.set_digital_output(self.config.close_output, false)?;
+        if let Some(open) = self.config.open_output {
+            robot.set_digital_output(open, true)?;
+        }
+        sleep(self.config.actuation_delay);
+
+        Ok(())
+    }
+
+    fn state(&self, feedback: &FeedbackData) -> EndEffectorState {
+        actuated_state(feedback.digital_output(self.config.close_output), &self.config.verification, self.load_check.as_ref(), feedback)
+    }
+
+    // Keeps a held part clamped, the gripper opening while the arm is stopped could drop it
+    fn safe_outputs(&self) -> Vec<(u32, bool)> {
+        vec![]
+    }
+}
+
+fn actuated_state(actuated: bool, verification: &GraspVerification, load_check: Option<&GraspLoadCheck>, feedback: &FeedbackData) -> EndEffectorState {
+    if !actuated {
+        return EndEffectorState::Released;
+    }
+    // None for checks that aren't configured or can't tell at the current pose
+    let input = verification.input.map(|i| feedback.digital_input(i));
+    let load = load_check.and_then(|c| c.object_present(feedback));
+    let configured = verification.input.is_some() as usize + verification.load.is_some() as usize;
+    let results = [input, load];
+    if results.contains(&Some(false)) {
+        EndEffectorState::Empty
+    } else if configured > 0 && results.iter().flatten().count() == configured {
+        EndEffectorState::Holding
+    } else {
+        EndEffectorState::Engaged
+    }
+}
@@ -1,6 +1,51 @@
+use anyhow::bail;
 use serde::Deserialize;
 use serde_big_array::BigArray;
 
+pub const FEEDBACK_MESSAGE_SIZE: usize = 1440;
+pub const FEEDBACK_TEST_VALUE: u64 = 0x0123456789ABCDEF;
+// Byte offset of test_value within a feedback message
+pub const FEEDBACK_TEST_VALUE_OFFSET: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RobotMode {
+    Init,
+    BrakeOpen,
+    PowerOff,
+    Disabled,
+    Enabled,
+    Backdrive,
+    Running,
+    Recording,
+    Error,
+    Pause,
+    Jog,
+    Unknown(u64),
+}
+
+impl RobotMode {
+    pub fn from_raw(mode: u64) -> RobotMode {
+        match mode {
+            1 => RobotMode::Init,
+            2 => RobotMode::BrakeOpen,
+            3 => RobotMode::PowerOff,
+            4 => RobotMode::Disabled,
+            5 => RobotMode::Enabled,
+            6 => RobotMode::Backdrive,
+            7 => RobotMode::Running,
+            8 => RobotMode::Recording,
+            9 => RobotMode::Error,
+            10 => RobotMode::Pause,
+            11 => RobotMode::Jog,
+            other => RobotMode::Unknown(other),
+        }
+    }
+
+    pub fn is_error(&self) -> bool {
+        *self == RobotMode::Error
+    }
+}
+
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct FeedbackData {
@@ -92,4 +137,96 @@ pub struct FeedbackData {
     pub actual_quaternion: [f64; 4], // Actual quaternion [qw, qx, qy, qz]
 
     pub reserved7: [u8; 24],
-}
\ No newline at end of file
+}
+
+impl FeedbackData {
+    // Valid frame with every other field zeroed
+    pub fn blank() -> FeedbackData {
+        let mut buffer = [0u8; FEEDBACK_MESSAGE_SIZE];
+        buffer[0..2].copy_from_slice(&(FEEDBACK_MESSAGE_SIZE as u16).to_le_bytes());
+        buffer[FEEDBACK_TEST_VALUE_OFFSET..FEEDBACK_TEST_VALUE_OFFSET + 8].copy_from_slice(&FEEDBACK_TEST_VALUE.to_le_bytes());
+        bincode::deserialize(&buffer).expect("Blank feedback frame should always deserialize")
+    }
+
+    // The message size is checked against the feedback layout when decoding
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if self.test_value != FEEDBACK_TEST_VALUE {
+            bail!("Invalid feedback test value {:#018X}", self.test_value);
+        }
+
+        Ok(())
+    }
+
+    pub fn mode(&self) -> RobotMode {
+        RobotMode::from_raw(self.robot_mode)
+    }
+
+    // IO indices start at 1, matching the DI/DO commands on the dashboard port
+    pub fn digital_input(&self, index: u32) -> bool {
+        io_bit(self.digital_inputs, index)
+    }
+
+    pub fn digital_output(&self, index: u32) -> bool {
+        io_bit(self.digital_outputs, index)
+    }
+
+    pub fn is_brake_released(&self) -> bool {
+        self.brake_status != 0
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.enable_status != 0
+    }
+
+    pub fn is_dragging(&self) -> bool {
+        self.drag_status != 0
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running_status != 0
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.error_status != 0 || self.mode().is_error()
+    }
+
+    pub fn is_jogging(&self) -> bool {
+        self.jog_status != 0
+    }
+
+    pub fn is_queue_running(&self) -> bool {
+        self.run_queued_cmd != 0
+    }
+
+    pub fn is_queue_paused(&self) -> bool {
+        self.pause_cmd_flag != 0
+    }
+
+    pub fn is_drag_button_pressed(&self) -> bool {
+        self.drag_button_signal != 0
+    }
+
+    pub fn is_enable_button_pressed(&self) -> bool {
+        self.enable_button_signal != 0
+    }
+
+    pub fn is_record_button_pressed(&self) -> bool {
+        self.record_button_signal != 0
+    }
+
+    pub fn is_reappear_button_pressed(&self) -> bool {
+        self.reappear_button_signal != 0
+    }
+
+    pub fn is_jaw_button_pressed(&self) -> bool {
+        self.jaw_button_signal != 0
+    }
+
+    pub fn is_six_force_online(&self) -> bool {
+        self.six_force_online != 0
+    }
+}
+
+fn io_bit(bits: u64, index: u32) -> bool {
+    (1..=64).contains(&index) && (bits >> (index - 1)) & 1 != 0
+}
@@ -0,0 +1,137 @@
+use std::{collections::VecDeque, sync::{mpsc::{self, Receiver, Sender}, Arc, Mutex}, time::{Duration, Instant}};
+
+use nalgebra::Vector4;
+
+use crate::feedback_data::FeedbackData;
+
+// About 10 seconds of history at the 8 ms feedback rate
+pub const DEFAULT_HISTORY_CAPACITY: usize = 1250;
+
+#[derive(Debug, Clone)]
+pub struct FeedbackSample {
+    // Host time the frame was received
+    pub time: Instant,
+    pub feedback: FeedbackData,
+}
+
+impl FeedbackSample {
+    pub fn pose(&self) -> Vector4<f64> {
+        let [x, y, z, r, ..] = self.feedback.tool_vector_actual;
+        Vector4::new(x, y, z, r)
+    }
+}
+
+struct HubState {
+    latest: Option<FeedbackSample>,
+    history: VecDeque<FeedbackSample>,
+    capacity: usize,
+    subscribers: Vec<Sender<FeedbackData>>,
+    sample_subscribers: Vec<Sender<FeedbackSample>>,
+}
+
+// Broadcasts feedback frames to subscribers and keeps a time indexed history of them
+#[derive(Clone)]
+pub struct FeedbackHub {
+    state: Arc<Mutex<HubState>>,
+}
+
+impl FeedbackHub {
+    pub fn new(capacity: usize) -> FeedbackHub {
+        FeedbackHub {
+            state: Arc::new(Mutex::new(HubState {
+                latest: None,
+                history: VecDeque::with_capacity(capacity),
+                capacity,
+                subscribers: Vec::new(),
+                sample_subscribers: Vec::new(),
+            })),
+        }
+    }
+
+    pub fn publish(&self, feedback: FeedbackData) {
+        self.publish_at(Instant::now(), feedback);
+    }
+
+    pub fn publish_at(&self, time: Instant, feedback: FeedbackData) {
+        let mut state = self.state.lock().unwrap();
+        state.subscribers.retain(|s| s.send(feedback.clone()).is_ok());
+
+        let sample = FeedbackSample { time, feedback };
+        state.sample_subscribers.retain(|s| s.send(sample.clone()).is_ok());
+        if state.capacity > 0 {
+            if state.history.len() == state.capacity {
+                state.history.pop_front();
+            }
+            state.history.push_back(sample.clone());
+        }
+        state.latest = Some(sample);
+    }
+
+    pub fn subscribe(&self) -> Receiver<FeedbackData> {
+        let (tx, rx) = mpsc::channel();
+        self.state.lock().unwrap().subscribers.push(tx);
+        rx
+    }
+
+    // Like `subscribe`, with the time each frame was received
+    pub fn subscribe_samples(&self) -> Receiver<FeedbackSample> {
+        let (tx, rx) = mpsc::channel();
+        self.state.lock().unwrap().sample_subscribers.push(tx);
+        rx
+    }
+
+    pub fn latest(&self) -> Option<FeedbackSample> {
+        self.state.lock().unwrap().latest.clone()
+    }
+
+    // Samples received in [from, to]
+    pub fn history_between(&self, from: Instant, to: Instant) -> Vec<FeedbackSample> {
+        let state = self.state.lock().unwrap();
+        let start = state.history.partition_point(|s| s.time < from);
+        state.history
+            .range(start..)
+            .take_while(|s| s.time <= to)
+            .cloned()
+            .collect()
+    }
+
+    // Sample received closest to `time`, if it is within `tolerance`
+    pub fn sample_at(&self, time: Instant, tolerance: Duration) -> Option<FeedbackSample> {
+        let state = self.state.lock().unwrap();
+        let i = state.history.partition_point(|s| s.time < time);
+        [i.checked_sub(1), Some(i)]
+            .into_iter()
+            .flatten()
+            .filter_map(|i| state.history.get(i))
+            .map(|s| (abs_diff(s.time, time), s))
+            .filter(|(diff, _)| *diff <= tolerance)
+            .min_by_key(|(diff, _)| *diff)
+            .map(|(_, s)| s.clone())
+    }
+
+    // Tool pose at `time`, linearly interpolated between the surrounding samples.
+    // Returns None if `time` is outside of the stored history.
+    pub fn pose_at(&self, time: Instant) -> Option<Vector4<f64>> {
+        let state = self.state.lock().unwrap();
+        let i = state.history.partition_point(|s| s.time < time);
+        let after = state.history.get(i)?;
+        if after.time == time {
+            return Some(after.pose());
+        }
+        let before = state.history.get(i.checked_sub(1)?)?;
+
+        let span = (after.time - before.time).as_secs_f64();
+        let t = if span > 0.0 { (time - before.time).as_secs_f64() / span } else { 0.0 };
+        let (a, b) = (before.pose(), after.pose());
+        let mut pose = a + (b - a) * t;
+        // Take the short way around for the rotation
+        let dr = (b.w - a.w + 180.0).rem_euclid(360.0) - 180.0;
+        pose.w = a.w + dr * t;
+
+        Some(pose)
+    }
+}
+
+fn abs_diff(a: Instant, b: Instant) -> Duration {
+    if a > b { a - b } else { b - a }
+}
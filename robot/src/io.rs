@@ -0,0 +1,212 @@
+use std::{collections::BTreeMap, path::Path, sync::mpsc::{self, Receiver, RecvTimeoutError}, thread, time::{Duration, Instant}};
+
+use anyhow::{anyhow, bail};
+
+use crate::{feedback_data::FeedbackData, Robot};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum IoKind {
+    Input,
+    Output,
+}
+
+impl IoKind {
+    fn parse(s: &str) -> anyhow::Result<IoKind> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "di" | "input" => Ok(IoKind::Input),
+            "do" | "output" => Ok(IoKind::Output),
+            other => bail!("Unknown IO kind {other}, expected DI or DO"),
+        }
+    }
+}
+
+// Digital input or output on the controller, indices start at 1
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IoChannel {
+    pub kind: IoKind,
+    pub index: u32,
+}
+
+impl IoChannel {
+    pub fn read(&self, feedback: &FeedbackData) -> bool {
+        match self.kind {
+            IoKind::Input => feedback.digital_input(self.index),
+            IoKind::Output => feedback.digital_output(self.index),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct IoEvent {
+    // None for channels that are not in the map
+    pub name: Option<String>,
+    pub channel: IoChannel,
+    pub state: bool,
+}
+
+impl IoEvent {
+    pub fn is_rising(&self) -> bool {
+        self.state
+    }
+}
+
+// Names for the IO channels used by the cell, e.g. `suction` for the vacuum valve
+#[derive(Debug, Clone, Default)]
+pub struct IoMap {
+    channels: BTreeMap<String, IoChannel>,
+}
+
+impl IoMap {
+    pub fn new() -> IoMap {
+        IoMap { channels: BTreeMap::new() }
+    }
+
+    pub fn with_input(mut self, name: &str, index: u32) -> IoMap {
+        self.insert(name, IoChannel { kind: IoKind::Input, index });
+        self
+    }
+
+    pub fn with_output(mut self, name: &str, index: u32) -> IoMap {
+        self.insert(name, IoChannel { kind: IoKind::Output, index });
+        self
+    }
+
+    pub fn insert(&mut self, name: &str, channel: IoChannel) {
+        self.channels.insert(name.to_string(), channel);
+    }
+
+    // CSV file starting with a `name,kind,index` header row, then one row per channel, kind is DI or DO.
+    // The header is checked, otherwise a file without one would silently lose its first channel.
+    pub fn load(path: &Path) -> anyhow::Result<IoMap> {
+        let mut map = IoMap::new();
+        let mut reader = csv::Reader::from_path(path)?;
+        let header: Vec<String> = reader.headers()?.iter().map(|h| h.trim().to_lowercase()).collect();
+        if header != ["name", "kind", "index"] {
+            bail!("{} has to start with a name,kind,index header, found {}", path.display(), header.join(","));
+        }
+        for (i, record) in reader.records().enumerate() {
+            let record = record?;
+            if record.len() != 3 {
+                bail!("Line {} of {} has {} columns, expected name,kind,index", i + 2, path.display(), record.len());
+            }
+            let index: u32 = record[2].trim().parse()?;
+            if !(1..=64).contains(&index) {
+                bail!("IO index {index} for {} is out of range", &record[0]);
+            }
+            map.insert(record[0].trim(), IoChannel { kind: IoKind::parse(&record[1])?, index });
+        }
+
+        Ok(map)
+    }
+
+    pub fn channel(&self, name: &str) -> anyhow::Result<IoChannel> {
+        self.channels.get(name).copied().ok_or(anyhow!("No IO channel named {name}"))
+    }
+
+    pub fn output(&self, name: &str) -> anyhow::Result<u32> {
+        match self.channel(name)? {
+            IoChannel { kind: IoKind::Output, index } => Ok(index),
+            IoChannel { kind: IoKind::Input, .. } => bail!("IO channel {name} is an input"),
+        }
+    }
+
+    pub fn input(&self, name: &str) -> anyhow::Result<u32> {
+        match self.channel(name)? {
+            IoChannel { kind: IoKind::Input, index } => Ok(index),
+            IoChannel { kind: IoKind::Output, .. } => bail!("IO channel {name} is an output"),
+        }
+    }
+
+    fn name_of(&self, channel: IoChannel) -> Option<&str> {
+        self.channels.iter().find(|(_, c)| **c == channel).map(|(n, _)| n.as_str())
+    }
+
+    pub fn read(&self, feedback: &FeedbackData, name: &str) -> anyhow::Result<bool> {
+        Ok(self.channel(name)?.read(feedback))
+    }
+
+    pub fn write(&self, robot: &mut dyn Robot, name: &str, status: bool) -> anyhow::Result<()> {
+        robot.set_digital_output(self.output(name)?, status)
+    }
+
+    // Blocks until every named channel has the given state
+    pub fn wait_until(&self, robot: &dyn Robot, conditions: &[(&str, bool)], timeout: Duration) -> anyhow::Result<()> {
+        let conditions = conditions
+            .iter()
+            .map(|(name, state)| Ok((self.channel(name)?, *state)))
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        let met = |feedback: &FeedbackData| conditions.iter().all(|(c, state)| c.read(feedback) == *state);
+
+        // Subscribe before checking the current state, so no change in between is missed
+        let feedback = robot.feedback_hub().subscribe();
+        if met(&robot.feedback()) {
+            return Ok(());
+        }
+        let deadline = Instant::now() + timeout;
+        loop {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            match feedback.recv_timeout(remaining) {
+                Ok(frame) if met(&frame) => return Ok(()),
+                Ok(_) => {}
+                Err(RecvTimeoutError::Timeout) => bail!("Timed out after {timeout:?} waiting for IO condition"),
+                Err(RecvTimeoutError::Disconnected) => bail!("Feedback stopped while waiting for IO condition"),
+            }
+        }
+    }
+
+    pub fn wait_for_input(&self, robot: &dyn Robot, name: &str, state: bool, timeout: Duration) -> anyhow::Result<()> {
+        self.input(name)?;
+        self.wait_until(robot, &[(name, state)], timeout)
+    }
+}
+
+// Reports changes of the digital inputs and outputs between feedback frames
+pub struct IoWatcher {
+    map: IoMap,
+    // Only report channels that are in the map
+    named_only: bool,
+    previous: Option<(u64, u64)>,
+}
+
+impl IoWatcher {
+    pub fn new(map: IoMap, named_only: bool) -> IoWatcher {
+        IoWatcher { map, named_only, previous: None }
+    }
+
+    pub fn update(&mut self, feedback: &FeedbackData) -> Vec<IoEvent> {
+        let current = (feedback.digital_inputs, feedback.digital_outputs);
+        // The first frame only sets the baseline
+        let Some(previous) = self.previous.replace(current) else {
+            return Vec::new();
+        };
+
+        let mut events = Vec::new();
+        for (kind, before, after) in [(IoKind::Input, previous.0, current.0), (IoKind::Output, previous.1, current.1)] {
+            let changed = before ^ after;
+            for bit in (0..64).filter(|b| changed >> b & 1 != 0) {
+                let channel = IoChannel { kind, index: bit + 1 };
+                let name = self.map.name_of(channel).map(str::to_string);
+                if self.named_only && name.is_none() {
+                    continue;
+                }
+                events.push(IoEvent { name, channel, state: after >> bit & 1 != 0 });
+            }
+        }
+        events
+    }
+
+    // Checks every frame from the subscription on a separate thread, so short pulses between polls are seen
+    pub fn spawn(mut self, feedback: Receiver<FeedbackData>) -> Receiver<IoEvent> {
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            for frame in feedback {
+                for event in self.update(&frame) {
+                    if tx.send(event).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        rx
+    }
+}
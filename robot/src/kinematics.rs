@@ -0,0 +1,181 @@
+use std::fmt;
+
+use nalgebra::{Vector2, Vector4};
+
+use crate::feedback_data::FeedbackData;
+
+// Link lengths and offsets of a 4-axis arm with a parallel linkage keeping the tool vertical (mm).
+// J1 rotates the base, J2 is the rear arm angle from vertical, J3 the forearm angle from horizontal
+// and J4 the tool rotation, matching the joint angles reported in q_actual (deg).
+#[derive(Debug, Clone)]
+pub struct ArmGeometry {
+    pub base_height: f64,
+    // Horizontal distance from the J1 axis to the J2 axis
+    pub base_offset: f64,
+    pub rear_arm: f64,
+    pub forearm: f64,
+    // Horizontal and vertical offset from the end of the forearm to the tool center point
+    pub tool_offset: f64,
+    pub tool_height: f64,
+}
+
+impl ArmGeometry {
+    // Dobot MG400. The arm lengths and joint limits are from the datasheet, the base and tool offsets have not been
+    // checked against a recorded q_actual/tool_vector_actual log yet, so SafetyConfig leaves the reachability check off.
+    pub fn mg400() -> ArmGeometry {
+        ArmGeometry {
+            base_height: 0.0,
+            base_offset: 43.0,
+            rear_arm: 175.0,
+            forearm: 175.0,
+            tool_offset: 66.0,
+            tool_height: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct JointLimits {
+    pub min: [f64; 4],
+    pub max: [f64; 4],
+}
+
+impl JointLimits {
+    pub fn mg400() -> JointLimits {
+        JointLimits {
+            min: [-160.0, -25.0, -25.0, -180.0],
+            max: [160.0, 85.0, 105.0, 180.0],
+        }
+    }
+
+    pub fn contains(&self, joints: &[f64; 4]) -> bool {
+        (0..4).all(|i| joints[i] >= self.min[i] && joints[i] <= self.max[i])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum IkError {
+    OutOfReach { distance: f64, min: f64, max: f64 },
+    JointLimit { joints: [f64; 4] },
+}
+
+impl fmt::Display for IkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IkError::OutOfReach { distance, min, max } => write!(f, "Wrist distance {distance:.1} mm is outside of the reachable range [{min:.1}, {max:.1}]"),
+            IkError::JointLimit { joints } => write!(f, "Joint angles {joints:?} are outside of the joint limits"),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ArmKinematics {
+    pub geometry: ArmGeometry,
+    pub limits: JointLimits,
+}
+
+impl ArmKinematics {
+    pub fn new(geometry: ArmGeometry, limits: JointLimits) -> ArmKinematics {
+        ArmKinematics { geometry, limits }
+    }
+
+    pub fn mg400() -> ArmKinematics {
+        ArmKinematics::new(ArmGeometry::mg400(), JointLimits::mg400())
+    }
+
+    // Tool pose [x, y, z, r] for joint angles [j1, j2, j3, j4] (deg)
+    pub fn forward(&self, joints: &[f64; 4]) -> Vector4<f64> {
+        let g = &self.geometry;
+        let [j1, j2, j3, j4] = joints.map(f64::to_radians);
+
+        let radial = g.base_offset + g.rear_arm * j2.sin() + g.forearm * j3.cos() + g.tool_offset;
+        let z = g.base_height + g.rear_arm * j2.cos() - g.forearm * j3.sin() - g.tool_height;
+
+        Vector4::new(radial * j1.cos(), radial * j1.sin(), z, (j1 + j4).to_degrees())
+    }
+
+    // Joint angles (deg) reaching `target`, using the elbow up solution
+    pub fn inverse(&self, target: &Vector4<f64>) -> Result<[f64; 4], IkError> {
+        let g = &self.geometry;
+        let j1 = target.y.atan2(target.x);
+        let radial = target.x.hypot(target.y);
+
+        // Wrist position relative to the J2 axis, in the arm plane
+        let p = Vector2::new(radial - g.base_offset - g.tool_offset, target.z - g.base_height + g.tool_height);
+        let d = p.norm();
+        let (min, max) = ((g.rear_arm - g.forearm).abs(), g.rear_arm + g.forearm);
+        if d < min || d > max || d == 0.0 {
+            return Err(IkError::OutOfReach { distance: d, min, max });
+        }
+
+        let alpha = ((g.rear_arm.powi(2) + d.powi(2) - g.forearm.powi(2)) / (2.0 * g.rear_arm * d)).clamp(-1.0, 1.0).acos();
+        let rear_dir = p.y.atan2(p.x) + alpha;
+        let elbow = Vector2::new(rear_dir.cos(), rear_dir.sin()) * g.rear_arm;
+        let fore = p - elbow;
+
+        let j2 = std::f64::consts::FRAC_PI_2 - rear_dir;
+        let j3 = -fore.y.atan2(fore.x);
+        let j1 = j1.to_degrees();
+        let j4 = wrap_degrees(target.w - j1);
+        let joints = [j1, j2.to_degrees(), j3.to_degrees(), j4];
+
+        if !self.limits.contains(&joints) {
+            return Err(IkError::JointLimit { joints });
+        }
+
+        Ok(joints)
+    }
+
+    pub fn is_reachable(&self, target: &Vector4<f64>) -> bool {
+        self.inverse(target).is_ok()
+    }
+
+    // Difference between the reported tool pose and the one computed from the reported joint angles
+    pub fn feedback_discrepancy(&self, feedback: &FeedbackData) -> Vector4<f64> {
+        let [j1, j2, j3, j4, ..] = feedback.q_actual;
+        let [x, y, z, r, ..] = feedback.tool_vector_actual;
+        let mut diff = Vector4::new(x, y, z, r) - self.forward(&[j1, j2, j3, j4]);
+        diff.w = wrap_degrees(diff.w);
+        diff
+    }
+
+    // Returns the position error (mm) if the reported pose and FK disagree by more than `tolerance`
+    pub fn check_feedback(&self, feedback: &FeedbackData, tolerance: f64) -> Option<f64> {
+        let error = self.feedback_discrepancy(feedback).xyz().norm();
+        (error > tolerance).then_some(error)
+    }
+}
+
+fn wrap_degrees(angle: f64) -> f64 {
+    (angle + 180.0).rem_euclid(360.0) - 180.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn forward_at_zero_joints() {
+        let kinematics = ArmKinematics::mg400();
+        let g = &kinematics.geometry;
+        let pose = kinematics.forward(&[0.0, 0.0, 0.0, 0.0]);
+
+        assert!((pose - Vector4::new(g.base_offset + g.forearm + g.tool_offset, 0.0, g.base_height + g.rear_arm - g.tool_height, 0.0)).norm() < 1e-9);
+    }
+
+    #[test]
+    fn inverse_undoes_forward() {
+        let kinematics = ArmKinematics::mg400();
+        for joints in [[0.0, 0.0, 0.0, 0.0], [30.0, 20.0, 10.0, -15.0], [-120.0, 60.0, 80.0, 90.0], [150.0, -20.0, -20.0, 170.0]] {
+            let pose = kinematics.forward(&joints);
+            let solved = kinematics.inverse(&pose).unwrap();
+            assert!((kinematics.forward(&solved) - pose).norm() < 1e-6, "{joints:?} solved as {solved:?}");
+        }
+    }
+
+    #[test]
+    fn rejects_targets_out_of_reach() {
+        let kinematics = ArmKinematics::mg400();
+        assert!(matches!(kinematics.inverse(&Vector4::new(1000.0, 0.0, 0.0, 0.0)), Err(IkError::OutOfReach { .. })));
+    }
+}
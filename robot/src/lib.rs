@@ -1,68 +1,360 @@
-use std::{io::{Read, Write}, net::TcpStream};
+use std::{io::{BufRead, BufReader, Read, Write}, net::TcpStream, sync::{mpsc::Receiver, Arc, Mutex, MutexGuard}, time::Duration};
 
-use anyhow::Ok;
-use feedback_data::FeedbackData;
+use anyhow::{bail, Ok};
+use dashboard::{DashboardResponse, ErrorIds};
+use feedback_data::{FeedbackData, FEEDBACK_MESSAGE_SIZE, FEEDBACK_TEST_VALUE, FEEDBACK_TEST_VALUE_OFFSET};
+use feedback_layout::{frame_size, FeedbackLayout, LayoutSelection, FEEDBACK_HEADER_SIZE};
+use feedback_hub::FeedbackHub;
+use motion_queue::MotionParams;
+use nalgebra::Vector4;
+use safety::{SafetyCheck, SafetyEnvelope};
 
 pub mod feedback_data;
+pub mod feedback_layout;
+pub mod safety;
+pub mod dobot;
+pub mod sim;
+pub mod end_effector;
+pub mod recorder;
+pub mod feedback_hub;
+pub mod dashboard;
+pub mod alarm;
+pub mod collision;
+pub mod kinematics;
+pub mod motion_queue;
+pub mod watchdog;
+pub mod teach;
+pub mod io;
+
+// Common interface for the real arm and simulated backends, poses are [x, y, z, r]
+pub trait Robot: Send {
+    fn enable(&mut self) -> anyhow::Result<()>;
+    fn disable(&mut self) -> anyhow::Result<()>;
+    fn move_to_with(&mut self, target: &Vector4<f64>, params: &MotionParams) -> anyhow::Result<()>;
+    fn move_relative(&mut self, delta: &Vector4<f64>) -> anyhow::Result<()>;
+    // Stops the current motion and clears queued motion commands
+    fn stop(&mut self) -> anyhow::Result<()>;
+    fn pause(&mut self) -> anyhow::Result<()>;
+    fn resume(&mut self) -> anyhow::Result<()>;
+    // Drag mode lets the arm be moved by hand, the robot has to be enabled
+    fn start_drag(&mut self) -> anyhow::Result<()>;
+    fn stop_drag(&mut self) -> anyhow::Result<()>;
+    // Handle for stopping the robot from other threads
+    fn emergency_handle(&self) -> anyhow::Result<Box<dyn EmergencyHandle>>;
+    // IO indices start at 1, as on the controller
+    fn set_digital_output(&mut self, index: u32, status: bool) -> anyhow::Result<()>;
+    fn pose(&self) -> Vector4<f64>;
+    fn feedback(&self) -> FeedbackData;
+    fn feedback_hub(&self) -> FeedbackHub;
+    fn error_ids(&mut self) -> anyhow::Result<ErrorIds>;
+    fn clear_error(&mut self) -> anyhow::Result<()>;
+
+    fn move_to(&mut self, target: &Vector4<f64>) -> anyhow::Result<()> {
+        self.move_to_with(target, &MotionParams::default())
+    }
+
+    // Every feedback frame received after subscribing is sent to the returned channel
+    fn subscribe_feedback(&mut self) -> Receiver<FeedbackData> {
+        self.feedback_hub().subscribe()
+    }
+}
+
+pub trait EmergencyHandle: Send {
+    fn stop(&mut self) -> anyhow::Result<()>;
+    fn disable(&mut self) -> anyhow::Result<()>;
+    fn set_digital_output(&mut self, index: u32, status: bool) -> anyhow::Result<()>;
+}
+
+const DASHBOARD_RESPONSE_TIMEOUT: Duration = Duration::from_secs(3);
+
+// Dashboard connection shared with the emergency handles. Each command is written and its reply read
+// while holding the lock, so commands from different threads can't interleave or take each other's replies.
+struct Dashboard {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+}
+
+impl Dashboard {
+    // Sends a command and waits for its raw reply. Replies left over from a command that timed out are skipped.
+    // Nothing is parsed here, so a malformed reply can't panic while the connection is locked.
+    fn exchange(&mut self, command: &str) -> anyhow::Result<String> {
+        self.stream.write_all(format!("{command}\n").as_bytes())?;
+        let name = command.split('(').next().unwrap_or(command);
+
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                bail!("Dashboard connection closed while waiting for reply to {command}");
+            }
+            // Replies can be separated by ';' without a newline
+            for reply in line.split_inclusive(';').filter(|r| !r.trim().is_empty()) {
+                match DashboardResponse::reply_function_name(reply) {
+                    Some(function) if function == name => return Ok(reply.to_string()),
+                    Some(_) => {}
+                    None => log::warn!("Ignoring dashboard reply {}", reply.trim()),
+                }
+            }
+        }
+    }
+}
+
+// Stopping the robot matters more than a thread that panicked while holding the connection
+fn lock_dashboard(dashboard: &Mutex<Dashboard>) -> MutexGuard<'_, Dashboard> {
+    dashboard.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+// The reply is parsed after the lock is released
+fn dashboard_request(dashboard: &Mutex<Dashboard>, command: &str) -> anyhow::Result<DashboardResponse> {
+    let reply = lock_dashboard(dashboard).exchange(command)?;
+    DashboardResponse::parse(&reply)
+}
+
+// For commands that only report success
+fn dashboard_command(dashboard: &Mutex<Dashboard>, command: &str) -> anyhow::Result<()> {
+    dashboard_request(dashboard, command)?.ok()?;
+
+    Ok(())
+}
 
 pub struct RobotConn {
-    dashboard_cmd_stream: TcpStream,
+    dashboard: Arc<Mutex<Dashboard>>,
     motion_cmd_stream: TcpStream,
+    safety: Option<SafetyEnvelope>,
 }
 
 impl RobotConn {
 
     pub fn connect() -> anyhow::Result<RobotConn> {
         let dasboard_conn = TcpStream::connect("192.168.2.6:29999")?;
+        dasboard_conn.set_read_timeout(Some(DASHBOARD_RESPONSE_TIMEOUT))?;
         let motion_conn = TcpStream::connect("192.168.2.6:30003")?;
 
         Ok(RobotConn {
-            dashboard_cmd_stream: dasboard_conn,
+            dashboard: Arc::new(Mutex::new(Dashboard {
+                reader: BufReader::new(dasboard_conn.try_clone()?),
+                stream: dasboard_conn,
+            })),
             motion_cmd_stream: motion_conn,
+            safety: None,
         })
     }
 
-    pub fn enable_robot(&mut self) -> anyhow::Result<()> {
-        write!(&mut self.dashboard_cmd_stream, "EnableRobot()\n")?;
+    pub fn set_safety_envelope(&mut self, envelope: Option<SafetyEnvelope>) {
+        self.safety = envelope;
+    }
 
-        Ok(())
+    pub fn enable_robot(&mut self) -> anyhow::Result<()> {
+        self.dashboard_command("EnableRobot()")
     }
 
     pub fn disable_robot(&mut self) -> anyhow::Result<()> { 
-        write!(&mut self.dashboard_cmd_stream, "DisableRobot()\n")?;
+        self.dashboard_command("DisableRobot()")
+    }
 
-        Ok(())
+    pub fn stop(&mut self) -> anyhow::Result<()> {
+        self.dashboard_command("ResetRobot()")
     }
 
-    pub fn set_do(&mut self, index: i32, status: bool) -> anyhow::Result<()> {
-        let status = status as i32;
-        write!(&mut self.dashboard_cmd_stream, "DO({index}, {status})\n")?;
+    pub fn pause(&mut self) -> anyhow::Result<()> {
+        self.dashboard_command("Pause()")
+    }
 
-        Ok(())
+    pub fn resume(&mut self) -> anyhow::Result<()> {
+        self.dashboard_command("Continue()")
+    }
+
+    pub fn start_drag(&mut self) -> anyhow::Result<()> {
+        self.dashboard_command("StartDrag()")
+    }
+
+    pub fn stop_drag(&mut self) -> anyhow::Result<()> {
+        self.dashboard_command("StopDrag()")
+    }
+
+    // Handle sharing the dashboard connection, usable from other threads
+    pub fn dashboard_handle(&self) -> DashboardHandle {
+        DashboardHandle { dashboard: self.dashboard.clone() }
+    }
+
+    pub fn get_error_ids(&mut self) -> anyhow::Result<ErrorIds> {
+        let response = self.dashboard_request("GetErrorID()")?.ok()?;
+        ErrorIds::parse(&response.values)
+    }
+
+    pub fn clear_error(&mut self) -> anyhow::Result<()> {
+        self.dashboard_command("ClearError()")
+    }
+
+    // Sends a command and waits for its reply
+    pub fn dashboard_request(&mut self, command: &str) -> anyhow::Result<DashboardResponse> {
+        dashboard_request(&self.dashboard, command)
+    }
+
+    fn dashboard_command(&mut self, command: &str) -> anyhow::Result<()> {
+        dashboard_command(&self.dashboard, command)
+    }
+
+    pub fn set_do(&mut self, index: i32, status: bool) -> anyhow::Result<()> {
+        self.dashboard_command(&format!("DO({index}, {})", status as i32))
     }
  
     pub fn mov_j(&mut self, x: f64, y: f64, z: f64, r: f64) -> anyhow::Result<()> {
-        write!(&mut self.motion_cmd_stream, "MovJ({x}, {y}, {z}, {r})\n")?;
+        self.mov_j_with(x, y, z, r, &MotionParams::default())
+    }
+
+    pub fn mov_j_with(&mut self, x: f64, y: f64, z: f64, r: f64, params: &MotionParams) -> anyhow::Result<()> {
+        let target = Vector4::new(x, y, z, r);
+        let check = match &self.safety {
+            Some(safety) => safety.check_absolute(&target),
+            None => SafetyCheck::Allowed(target),
+        };
+        self.send_checked_mov_j(check, params)
+    }
+
+    // Moves by `delta` relative to `current`, which lets the safety envelope limit the step size
+    pub fn mov_j_relative(&mut self, current: &Vector4<f64>, delta: &Vector4<f64>) -> anyhow::Result<()> {
+        let check = match &self.safety {
+            Some(safety) => safety.check_relative(current, delta),
+            None => SafetyCheck::Allowed(current + delta),
+        };
+        self.send_checked_mov_j(check, &MotionParams::default())
+    }
+
+    fn send_checked_mov_j(&mut self, check: SafetyCheck, params: &MotionParams) -> anyhow::Result<()> {
+        let target = match check {
+            SafetyCheck::Allowed(target) => target,
+            SafetyCheck::Clamped(target, violations) => {
+                for v in &violations {
+                    log::warn!("Clamping MovJ target: {v}");
+                }
+                target
+            }
+            SafetyCheck::Rejected(violations) => {
+                let reasons = violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+                bail!("MovJ rejected by safety envelope: {reasons}");
+            }
+        };
+        let (x, y, z, r) = (target.x, target.y, target.z, target.w);
+        let extra = params.command_args();
+        self.motion_cmd_stream.write_all(format!("MovJ({x}, {y}, {z}, {r}{extra})\n").as_bytes())?;
 
         Ok(())
     }
 }
 
+pub struct DashboardHandle {
+    dashboard: Arc<Mutex<Dashboard>>,
+}
+
+impl EmergencyHandle for DashboardHandle {
+    fn stop(&mut self) -> anyhow::Result<()> {
+        dashboard_command(&self.dashboard, "ResetRobot()")
+    }
+
+    fn disable(&mut self) -> anyhow::Result<()> {
+        dashboard_command(&self.dashboard, "DisableRobot()")
+    }
+
+    fn set_digital_output(&mut self, index: u32, status: bool) -> anyhow::Result<()> {
+        dashboard_command(&self.dashboard, &format!("DO({index}, {})", status as i32))
+    }
+}
+
 pub struct RobotFeedbackConn {
     feedback_conn: TcpStream,
+    buffer: Vec<u8>,
+    dropped_frames: u64,
+    layouts: LayoutSelection,
+    layout: Option<FeedbackLayout>,
 }
 
 impl RobotFeedbackConn {
     pub fn connect() -> anyhow::Result<RobotFeedbackConn> {
+        RobotFeedbackConn::connect_with_layout(LayoutSelection::Auto)
+    }
+
+    pub fn connect_with_layout(layouts: LayoutSelection) -> anyhow::Result<RobotFeedbackConn> {
         let feedback_conn = TcpStream::connect("192.168.2.6:30004")?;
-        Ok(RobotFeedbackConn { feedback_conn })
+        Ok(RobotFeedbackConn {
+            feedback_conn,
+            buffer: Vec::with_capacity(FEEDBACK_MESSAGE_SIZE * 2),
+            dropped_frames: 0,
+            layouts,
+            layout: None,
+        })
+    }
+
+    // Layout of the last received frame
+    pub fn layout(&self) -> Option<&FeedbackLayout> {
+        self.layout.as_ref()
+    }
+
+    // Number of frames skipped while resynchronising with the stream, including frames that lost bytes
+    pub fn dropped_frames(&self) -> u64 {
+        self.dropped_frames
     }
 
     pub fn receive_feedback(&mut self) -> anyhow::Result<FeedbackData> {
-        let mut buffer = [0u8; 1440];
-        self.feedback_conn.read_exact(&mut buffer)?;
-        let feedback_data = bincode::deserialize(&buffer)?;
-        
-        Ok(feedback_data)
+        let sizes = self.layouts.sizes();
+        let max_size = sizes.iter().copied().max().unwrap_or(FEEDBACK_MESSAGE_SIZE);
+        let size = loop {
+            self.fill_buffer(FEEDBACK_HEADER_SIZE)?;
+            if let Some(size) = frame_size(&self.buffer, &sizes) {
+                // A frame that lost bytes in the middle still has a valid header, so it's only accepted once
+                // the header of the next frame lines up after it. This delays each frame until the next one starts.
+                self.fill_buffer(size + FEEDBACK_HEADER_SIZE)?;
+                if frame_size(&self.buffer[size..], &sizes).is_some() {
+                    break size;
+                }
+            }
+            // Search a whole frame worth of bytes for the next start, dropping the broken frame
+            self.fill_buffer(max_size)?;
+            self.resync();
+        };
+        let message: Vec<u8> = self.buffer.drain(..size).collect();
+
+        // Detect the layout on the first frame and whenever the message size changes
+        let layout = match self.layout.take() {
+            Some(layout) if layout.message_size == size => layout,
+            _ => {
+                let layout = self.layouts.select(&message)?;
+                log::info!("Using feedback layout {} ({size} bytes)", layout.name);
+                layout
+            }
+        };
+        let feedback_data = layout.decode(&message);
+        self.layout = Some(layout);
+
+        feedback_data
+    }
+
+    fn fill_buffer(&mut self, len: usize) -> anyhow::Result<()> {
+        let start = self.buffer.len();
+        if start < len {
+            self.buffer.resize(len, 0);
+            self.feedback_conn.read_exact(&mut self.buffer[start..])?;
+        }
+
+        Ok(())
     }
-}
\ No newline at end of file
+
+    // Drops bytes until the buffer starts at the next position where the test value lines up
+    fn resync(&mut self) {
+        let magic = FEEDBACK_TEST_VALUE.to_le_bytes();
+        let next_start = self.buffer
+            .windows(magic.len())
+            .enumerate()
+            .skip(FEEDBACK_TEST_VALUE_OFFSET + 1)
+            .find(|(_, w)| *w == magic)
+            .map(|(i, _)| i - FEEDBACK_TEST_VALUE_OFFSET);
+
+        // Without a match, keep the tail since it may hold the start of the next frame
+        let skipped = next_start.unwrap_or(self.buffer.len() - FEEDBACK_TEST_VALUE_OFFSET - magic.len() + 1);
+        self.buffer.drain(..skipped);
+
+        let frame_size = self.layout.as_ref().map(|l| l.message_size).unwrap_or(FEEDBACK_MESSAGE_SIZE);
+        let dropped = (skipped as u64).div_ceil(frame_size as u64);
+        self.dropped_frames += dropped;
+        log::warn!("Feedback stream out of sync, skipped {skipped} bytes ({dropped} frames, {} total)", self.dropped_frames);
+    }
+}
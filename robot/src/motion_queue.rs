@@ -0,0 +1,321 @@
+use std::{collections::VecDeque, thread::sleep, time::{Duration, Instant}};
+
+use anyhow::bail;
+use nalgebra::Vector4;
+
+use crate::Robot;
+
+// Optional MovJ parameters, the controller defaults are used for unset values
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MotionParams {
+    // Joint speed ratio (1~100)
+    pub speed: Option<u8>,
+    // Joint acceleration ratio (1~100)
+    pub acceleration: Option<u8>,
+    // Continuous path ratio (0~100), blends into the next segment instead of stopping at the waypoint
+    pub blend: Option<u8>,
+}
+
+impl MotionParams {
+    // Extra arguments for MovJ, including the leading comma
+    pub fn command_args(&self) -> String {
+        [("SpeedJ", self.speed), ("AccJ", self.acceleration), ("CP", self.blend)]
+            .into_iter()
+            .filter_map(|(name, value)| value.map(|v| format!(", {name}={}", v.min(100))))
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum MotionStep {
+    Move { target: Vector4<f64>, params: MotionParams },
+    // IO steps wait until every earlier move has finished
+    SetOutput { index: u32, status: bool },
+    Wait(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum QueueState {
+    Idle,
+    Running,
+    Paused,
+    Cancelled,
+    Finished,
+    Failed(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct QueueProgress {
+    pub state: QueueState,
+    pub completed: usize,
+    pub total: usize,
+    // Index of the oldest step that has been started but not completed
+    pub current: Option<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct MotionQueueConfig {
+    // Distance from the target (mm) at which a stopping waypoint counts as reached
+    pub reach_tolerance: f64,
+    // Larger tolerance for blended waypoints, the arm never passes exactly through them
+    pub blend_tolerance: f64,
+    // Gives up if a segment takes longer than this
+    pub segment_timeout: Duration,
+    // Gives up if the queue stays paused longer than this, the paused time doesn't count towards the segment timeout
+    pub pause_timeout: Duration,
+}
+
+impl MotionQueueConfig {
+    pub fn new() -> MotionQueueConfig {
+        MotionQueueConfig {
+            reach_tolerance: 1.0,
+            blend_tolerance: 15.0,
+            segment_timeout: Duration::from_secs(20),
+            pause_timeout: Duration::from_secs(60),
+        }
+    }
+}
+
+impl Default for MotionQueueConfig {
+    fn default() -> MotionQueueConfig {
+        MotionQueueConfig::new()
+    }
+}
+
+struct InFlight {
+    index: usize,
+    target: Vector4<f64>,
+    blended: bool,
+    sent_at: Instant,
+}
+
+// Sequence of moves and IO actions that is fed to the robot as earlier steps complete.
+// Call `update` regularly, or `run` to block until the queue is done.
+pub struct MotionQueue {
+    config: MotionQueueConfig,
+    steps: Vec<MotionStep>,
+    next: usize,
+    completed: usize,
+    in_flight: VecDeque<InFlight>,
+    wait_until: Option<Instant>,
+    state: QueueState,
+    paused_at: Option<Instant>,
+    // Paused from the controller side, e.g. the control panel, instead of through `pause`
+    controller_paused: bool,
+}
+
+impl MotionQueue {
+    pub fn new(config: MotionQueueConfig) -> MotionQueue {
+        MotionQueue {
+            config,
+            steps: Vec::new(),
+            next: 0,
+            completed: 0,
+            in_flight: VecDeque::new(),
+            wait_until: None,
+            state: QueueState::Idle,
+            paused_at: None,
+            controller_paused: false,
+        }
+    }
+
+    pub fn push_move(&mut self, target: Vector4<f64>, params: MotionParams) -> &mut Self {
+        self.push(MotionStep::Move { target, params })
+    }
+
+    pub fn push_output(&mut self, index: u32, status: bool) -> &mut Self {
+        self.push(MotionStep::SetOutput { index, status })
+    }
+
+    pub fn push_wait(&mut self, duration: Duration) -> &mut Self {
+        self.push(MotionStep::Wait(duration))
+    }
+
+    pub fn push(&mut self, step: MotionStep) -> &mut Self {
+        self.steps.push(step);
+        if self.state == QueueState::Finished {
+            self.state = QueueState::Running;
+        }
+        self
+    }
+
+    // Moves down onto an object, switches the output on, lifts it and sets it down at the place pose
+    pub fn pick_and_place(&mut self, pick: Vector4<f64>, place: Vector4<f64>, lift: f64, output: u32, settle: Duration) -> &mut Self {
+        let above = |p: Vector4<f64>| p + Vector4::new(0.0, 0.0, lift, 0.0);
+        let blended = MotionParams { blend: Some(50), ..MotionParams::default() };
+        self.push_move(above(pick), blended.clone())
+            .push_move(pick, MotionParams::default())
+            .push_output(output, true)
+            .push_wait(settle)
+            .push_move(above(pick), blended.clone())
+            .push_move(above(place), blended)
+            .push_move(place, MotionParams::default())
+            .push_output(output, false)
+            .push_wait(settle)
+            .push_move(above(place), MotionParams::default())
+    }
+
+    pub fn progress(&self) -> QueueProgress {
+        QueueProgress {
+            state: self.state.clone(),
+            completed: self.completed,
+            total: self.steps.len(),
+            current: (self.completed < self.next).then_some(self.completed),
+        }
+    }
+
+    pub fn pause(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()> {
+        if self.state == QueueState::Running {
+            robot.pause()?;
+            self.set_paused(false);
+        }
+        Ok(())
+    }
+
+    pub fn resume(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()> {
+        if self.state == QueueState::Paused {
+            robot.resume()?;
+            self.set_running();
+        }
+        Ok(())
+    }
+
+    fn set_paused(&mut self, controller: bool) {
+        self.state = QueueState::Paused;
+        self.paused_at = Some(Instant::now());
+        self.controller_paused = controller;
+    }
+
+    fn set_running(&mut self) {
+        self.state = QueueState::Running;
+        self.paused_at = None;
+        self.controller_paused = false;
+        // Don't count the paused time against the running segments
+        let now = Instant::now();
+        self.in_flight.iter_mut().for_each(|f| f.sent_at = now);
+    }
+
+    fn fail(&mut self, reason: String) -> anyhow::Result<QueueProgress> {
+        self.state = QueueState::Failed(reason.clone());
+        bail!(reason)
+    }
+
+    // Stops the robot and drops every step that hasn't completed
+    pub fn cancel(&mut self, robot: &mut dyn Robot) -> anyhow::Result<()> {
+        self.in_flight.clear();
+        self.wait_until = None;
+        self.state = QueueState::Cancelled;
+        robot.stop()
+    }
+
+    pub fn update(&mut self, robot: &mut dyn Robot) -> anyhow::Result<QueueProgress> {
+        match self.state {
+            QueueState::Idle if !self.steps.is_empty() => self.state = QueueState::Running,
+            QueueState::Running | QueueState::Paused => {}
+            _ => return Ok(self.progress()),
+        }
+
+        let feedback = robot.feedback();
+        if self.state == QueueState::Paused {
+            if self.paused_at.is_some_and(|t| t.elapsed() > self.config.pause_timeout) {
+                return self.fail(format!("Motion queue was paused for longer than {:?}", self.config.pause_timeout));
+            }
+            // A pause from the controller ends when its flag clears, one from `pause` needs `resume`
+            if !self.controller_paused || feedback.is_queue_paused() {
+                return Ok(self.progress());
+            }
+            self.set_running();
+        } else if feedback.is_queue_paused() {
+            self.set_paused(true);
+            return Ok(self.progress());
+        }
+
+        let pose = robot.pose();
+        let controller_idle = !feedback.is_queue_running() && !feedback.is_running();
+        let len = self.in_flight.len();
+        // A later target being reached means the earlier ones were passed, even if the arm cut a
+        // blended corner wider than the tolerance
+        let reached = self.in_flight.iter().enumerate().rposition(|(i, f)| {
+            let distance = (pose - f.target).xyz().norm();
+            let tolerance = if f.blended { self.config.blend_tolerance } else { self.config.reach_tolerance };
+            distance <= tolerance && (f.blended || i + 1 < len || controller_idle)
+        });
+        if let Some(i) = reached {
+            self.in_flight.drain(..=i);
+            self.completed += i + 1;
+        }
+        if let Some(front) = self.in_flight.front() {
+            if front.sent_at.elapsed() > self.config.segment_timeout {
+                return self.fail(format!("Step {} did not reach its target within {:?}", front.index, self.config.segment_timeout));
+            }
+        }
+
+        if let Some(wait_until) = self.wait_until {
+            if Instant::now() < wait_until {
+                return Ok(self.progress());
+            }
+            self.wait_until = None;
+            self.completed += 1;
+        }
+
+        while self.next < self.steps.len() {
+            match &self.steps[self.next] {
+                MotionStep::Move { target, params } => {
+                    // Send the next segment early only when the current one blends into it
+                    let lookahead = if self.in_flight.back().map(|f| f.blended).unwrap_or(true) { 2 } else { 1 };
+                    if self.in_flight.len() >= lookahead {
+                        break;
+                    }
+                    robot.move_to_with(target, params)?;
+                    self.in_flight.push_back(InFlight {
+                        index: self.next,
+                        target: *target,
+                        blended: params.blend.unwrap_or(0) > 0,
+                        sent_at: Instant::now(),
+                    });
+                }
+                MotionStep::SetOutput { index, status } => {
+                    if !self.in_flight.is_empty() {
+                        break;
+                    }
+                    robot.set_digital_output(*index, *status)?;
+                    self.completed += 1;
+                }
+                MotionStep::Wait(duration) => {
+                    if !self.in_flight.is_empty() {
+                        break;
+                    }
+                    self.wait_until = Some(Instant::now() + *duration);
+                    self.next += 1;
+                    break;
+                }
+            }
+            self.next += 1;
+        }
+
+        if self.completed == self.steps.len() {
+            self.state = QueueState::Finished;
+        }
+
+        Ok(self.progress())
+    }
+
+    // Blocks until every step has completed, polling the robot at `poll_interval`
+    pub fn run(&mut self, robot: &mut dyn Robot, poll_interval: Duration) -> anyhow::Result<()> {
+        self.run_with(robot, poll_interval, |_| {})
+    }
+
+    // Like `run`, calling `on_poll` after every update, e.g. to keep a watchdog fed during long sequences
+    pub fn run_with(&mut self, robot: &mut dyn Robot, poll_interval: Duration, mut on_poll: impl FnMut(&QueueProgress)) -> anyhow::Result<()> {
+        loop {
+            let progress = self.update(robot)?;
+            on_poll(&progress);
+            match progress.state {
+                QueueState::Finished => return Ok(()),
+                QueueState::Cancelled => bail!("Motion queue was cancelled"),
+                QueueState::Failed(reason) => bail!(reason),
+                QueueState::Idle | QueueState::Running | QueueState::Paused => sleep(poll_interval),
+            }
+        }
+    }
+}
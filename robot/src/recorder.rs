@@ -0,0 +1,350 @@
+use std::{fs::File, path::Path, sync::{atomic::{AtomicBool, Ordering}, mpsc::{Receiver, RecvTimeoutError}, Arc}, thread::{self, JoinHandle}, time::{Duration, Instant, SystemTime, UNIX_EPOCH}};
+
+use anyhow::anyhow;
+
+use crate::{feedback_data::FeedbackData, feedback_hub::FeedbackSample};
+
+// How often the recording thread checks whether it should stop
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordFormat {
+    Csv,
+    // Requires the `parquet` feature
+    Parquet,
+}
+
+impl RecordFormat {
+    pub fn from_path(path: &Path) -> Option<RecordFormat> {
+        match path.extension()?.to_str()? {
+            "csv" => Some(RecordFormat::Csv),
+            "parquet" => Some(RecordFormat::Parquet),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordedField {
+    // Actual tool pose [x, y, z, r]
+    Pose,
+    TargetPose,
+    JointPositions,
+    JointCurrents,
+    TargetJointCurrents,
+    JointTorques,
+    TcpForce,
+    Io,
+    Modes,
+    Load,
+}
+
+impl RecordedField {
+    pub fn all() -> Vec<RecordedField> {
+        vec![
+            RecordedField::Pose,
+            RecordedField::TargetPose,
+            RecordedField::JointPositions,
+            RecordedField::JointCurrents,
+            RecordedField::TargetJointCurrents,
+            RecordedField::JointTorques,
+            RecordedField::TcpForce,
+            RecordedField::Io,
+            RecordedField::Modes,
+            RecordedField::Load,
+        ]
+    }
+
+    fn columns(&self) -> Vec<(String, ColumnType)> {
+        let joints = |prefix: &str| (1..=6).map(|j| (format!("{prefix}_j{j}"), ColumnType::Float)).collect();
+        let axes = |prefix: &str, axes: &[&str]| axes.iter().map(|a| (format!("{prefix}_{a}"), ColumnType::Float)).collect();
+        match self {
+            RecordedField::Pose => axes("pose", &["x", "y", "z", "r"]),
+            RecordedField::TargetPose => axes("target_pose", &["x", "y", "z", "r"]),
+            RecordedField::JointPositions => joints("q_actual"),
+            RecordedField::JointCurrents => joints("i_actual"),
+            RecordedField::TargetJointCurrents => joints("i_target"),
+            RecordedField::JointTorques => joints("m_actual"),
+            RecordedField::TcpForce => axes("tcp_force", &["fx", "fy", "fz", "tx", "ty", "tz"]),
+            RecordedField::Io => vec![
+                ("digital_inputs".to_string(), ColumnType::UInt),
+                ("digital_outputs".to_string(), ColumnType::UInt),
+            ],
+            RecordedField::Modes => ["robot_mode", "enable_status", "error_status", "running_status", "drag_status", "run_queued_cmd", "pause_cmd_flag"]
+                .iter()
+                .map(|c| (c.to_string(), ColumnType::UInt))
+                .collect(),
+            RecordedField::Load => vec![("load".to_string(), ColumnType::Float)],
+        }
+    }
+
+    fn values(&self, f: &FeedbackData, row: &mut Vec<Value>) {
+        let floats = |row: &mut Vec<Value>, values: &[f64]| row.extend(values.iter().map(|v| Value::Float(*v)));
+        match self {
+            RecordedField::Pose => floats(row, &f.tool_vector_actual[..4]),
+            RecordedField::TargetPose => floats(row, &f.tool_vector_target[..4]),
+            RecordedField::JointPositions => floats(row, &f.q_actual),
+            RecordedField::JointCurrents => floats(row, &f.i_actual),
+            RecordedField::TargetJointCurrents => floats(row, &f.i_target),
+            RecordedField::JointTorques => floats(row, &f.m_actual),
+            RecordedField::TcpForce => floats(row, &f.tcp_force),
+            RecordedField::Io => row.extend([Value::UInt(f.digital_inputs), Value::UInt(f.digital_outputs)]),
+            RecordedField::Modes => row.extend(
+                [f.robot_mode, f.enable_status as u64, f.error_status as u64, f.running_status as u64, f.drag_status as u64, f.run_queued_cmd as u64, f.pause_cmd_flag as u64]
+                    .map(Value::UInt)
+            ),
+            RecordedField::Load => row.push(Value::Float(f.load)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ColumnType {
+    UInt,
+    Float,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum Value {
+    UInt(u64),
+    Float(f64),
+}
+
+enum Writer {
+    Csv(csv::Writer<File>),
+    #[cfg(feature = "parquet")]
+    Parquet(parquet_writer::ParquetWriter),
+}
+
+// Writes feedback frames to disk, every row starts with the host receive time and the controller time stamp
+pub struct FeedbackRecorder {
+    fields: Vec<RecordedField>,
+    writer: Writer,
+    rows: u64,
+}
+
+impl FeedbackRecorder {
+    pub fn create(path: &Path, format: RecordFormat, fields: &[RecordedField]) -> anyhow::Result<FeedbackRecorder> {
+        let mut columns = vec![
+            ("host_time_us".to_string(), ColumnType::UInt),
+            ("controller_time_ms".to_string(), ColumnType::UInt),
+        ];
+        columns.extend(fields.iter().flat_map(|f| f.columns()));
+
+        let writer = match format {
+            RecordFormat::Csv => {
+                let mut writer = csv::Writer::from_path(path)?;
+                writer.write_record(columns.iter().map(|(name, _)| name))?;
+                Writer::Csv(writer)
+            }
+            #[cfg(feature = "parquet")]
+            RecordFormat::Parquet => Writer::Parquet(parquet_writer::ParquetWriter::create(path, &columns)?),
+            #[cfg(not(feature = "parquet"))]
+            RecordFormat::Parquet => anyhow::bail!("Parquet recording requires the robot crate to be built with the parquet feature"),
+        };
+
+        Ok(FeedbackRecorder {
+            fields: fields.to_vec(),
+            writer,
+            rows: 0,
+        })
+    }
+
+    pub fn record(&mut self, received_at: SystemTime, feedback: &FeedbackData) -> anyhow::Result<()> {
+        let host_time = received_at.duration_since(UNIX_EPOCH)?.as_micros() as u64;
+        let mut row = vec![Value::UInt(host_time), Value::UInt(feedback.time_stamp)];
+        for field in &self.fields {
+            field.values(feedback, &mut row);
+        }
+
+        match &mut self.writer {
+            Writer::Csv(writer) => {
+                writer.write_record(row.iter().map(|v| match v {
+                    Value::UInt(v) => v.to_string(),
+                    Value::Float(v) => v.to_string(),
+                }))?;
+            }
+            #[cfg(feature = "parquet")]
+            Writer::Parquet(writer) => writer.write_row(&row)?,
+        }
+        self.rows += 1;
+
+        Ok(())
+    }
+
+    pub fn rows(&self) -> u64 {
+        self.rows
+    }
+
+    pub fn finish(self) -> anyhow::Result<()> {
+        match self.writer {
+            Writer::Csv(mut writer) => writer.flush()?,
+            #[cfg(feature = "parquet")]
+            Writer::Parquet(writer) => writer.finish()?,
+        }
+
+        Ok(())
+    }
+
+    // Records every sample from the subscription on a separate thread until the returned handle is stopped.
+    // The hub keeps its sender, so the subscription doesn't end on its own.
+    pub fn spawn(mut self, feedback: Receiver<FeedbackSample>) -> RecordingHandle {
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_running = running.clone();
+        let thread = thread::spawn(move || {
+            // Samples carry the Instant the hub received them at, the file gets wall clock time
+            let (anchor, anchor_time) = (Instant::now(), SystemTime::now());
+            let received_at = |time: Instant| match time.checked_duration_since(anchor) {
+                Some(after) => anchor_time + after,
+                None => anchor_time - (anchor - time),
+            };
+            while thread_running.load(Ordering::Relaxed) {
+                match feedback.recv_timeout(STOP_POLL_INTERVAL) {
+                    Ok(sample) => self.record(received_at(sample.time), &sample.feedback)?,
+                    Err(RecvTimeoutError::Timeout) => continue,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+            }
+            // Keep what was received before the stop
+            for sample in feedback.try_iter() {
+                self.record(received_at(sample.time), &sample.feedback)?;
+            }
+            let rows = self.rows;
+            self.finish()?;
+            log::info!("Feedback recording finished with {rows} rows");
+
+            Ok(rows)
+        });
+
+        RecordingHandle { running, thread: Some(thread) }
+    }
+}
+
+// Stops the recording thread and finishes the file, also when dropped.
+// The subscription is closed along with the thread, the hub drops it on the next frame.
+pub struct RecordingHandle {
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<anyhow::Result<u64>>>,
+}
+
+impl RecordingHandle {
+    // Returns the number of recorded rows
+    pub fn stop(mut self) -> anyhow::Result<u64> {
+        self.join()
+    }
+
+    fn join(&mut self) -> anyhow::Result<u64> {
+        self.running.store(false, Ordering::Relaxed);
+        match self.thread.take() {
+            Some(thread) => thread.join().map_err(|_| anyhow!("Feedback recording thread panicked"))?,
+            None => Ok(0),
+        }
+    }
+}
+
+impl Drop for RecordingHandle {
+    fn drop(&mut self) {
+        if self.thread.is_some() {
+            if let Err(e) = self.join() {
+                log::error!("Failed to finish feedback recording: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "parquet")]
+mod parquet_writer {
+    use std::{fs::File, path::Path, sync::Arc};
+
+    use arrow_array::{ArrayRef, Float64Array, RecordBatch, UInt64Array};
+    use arrow_schema::{DataType, Field, Schema, SchemaRef};
+    use parquet::arrow::ArrowWriter;
+
+    use super::{ColumnType, Value};
+
+    const BATCH_SIZE: usize = 1024;
+
+    enum Column {
+        UInt(Vec<u64>),
+        Float(Vec<f64>),
+    }
+
+    pub struct ParquetWriter {
+        schema: SchemaRef,
+        columns: Vec<Column>,
+        writer: ArrowWriter<File>,
+    }
+
+    impl ParquetWriter {
+        pub fn create(path: &Path, columns: &[(String, ColumnType)]) -> anyhow::Result<ParquetWriter> {
+            let schema = Arc::new(Schema::new(
+                columns
+                    .iter()
+                    .map(|(name, typ)| match typ {
+                        ColumnType::UInt => Field::new(name, DataType::UInt64, false),
+                        ColumnType::Float => Field::new(name, DataType::Float64, false),
+                    })
+                    .collect::<Vec<_>>(),
+            ));
+            let writer = ArrowWriter::try_new(File::create(path)?, schema.clone(), None)?;
+            let columns = columns
+                .iter()
+                .map(|(_, typ)| match typ {
+                    ColumnType::UInt => Column::UInt(Vec::with_capacity(BATCH_SIZE)),
+                    ColumnType::Float => Column::Float(Vec::with_capacity(BATCH_SIZE)),
+                })
+                .collect();
+
+            Ok(ParquetWriter { schema, columns, writer })
+        }
+
+        pub fn write_row(&mut self, row: &[Value]) -> anyhow::Result<()> {
+            for (column, value) in self.columns.iter_mut().zip(row) {
+                match (column, value) {
+                    (Column::UInt(c), Value::UInt(v)) => c.push(*v),
+                    (Column::Float(c), Value::Float(v)) => c.push(*v),
+                    _ => anyhow::bail!("Value does not match the column type"),
+                }
+            }
+            if self.buffered_rows() >= BATCH_SIZE {
+                self.flush_batch()?;
+            }
+
+            Ok(())
+        }
+
+        pub fn finish(mut self) -> anyhow::Result<()> {
+            self.flush_batch()?;
+            self.writer.close()?;
+
+            Ok(())
+        }
+
+        fn buffered_rows(&self) -> usize {
+            match self.columns.first() {
+                Some(Column::UInt(c)) => c.len(),
+                Some(Column::Float(c)) => c.len(),
+                None => 0,
+            }
+        }
+
+        fn flush_batch(&mut self) -> anyhow::Result<()> {
+            if self.buffered_rows() == 0 {
+                return Ok(());
+            }
+            let arrays = self.columns
+                .iter_mut()
+                .map(|c| -> ArrayRef {
+                    match c {
+                        Column::UInt(c) => Arc::new(UInt64Array::from(std::mem::take(c))),
+                        Column::Float(c) => Arc::new(Float64Array::from(std::mem::take(c))),
+                    }
+                })
+                .collect::<Vec<_>>();
+            let batch = RecordBatch::try_new(self.schema.clone(), arrays)?;
+            self.writer.write(&batch)?;
+
+            Ok(())
+        }
+    }
+}
@@ -0,0 +1,269 @@
+use std::fmt;
+
+use anyhow::bail;
+use nalgebra::{Vector3, Vector4};
+
+use crate::kinematics::ArmKinematics;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ViolationAction {
+    // Move the target to the closest allowed pose and continue
+    Clamp,
+    // Refuse to send the command
+    Reject,
+}
+
+#[derive(Debug, Clone)]
+pub struct ForbiddenZone {
+    pub name: String,
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+}
+
+impl ForbiddenZone {
+    pub fn new(name: &str, min: Vector3<f64>, max: Vector3<f64>) -> ForbiddenZone {
+        ForbiddenZone {
+            name: name.to_string(),
+            min,
+            max,
+        }
+    }
+
+    pub fn contains(&self, p: &Vector3<f64>) -> bool {
+        (0..3).all(|i| p[i] >= self.min[i] && p[i] <= self.max[i])
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SafetyConfig {
+    // Cartesian bounding volume for the tool (mm)
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+    // Lowest z the tool may go to, applied on top of the bounding volume (mm)
+    pub z_floor: f64,
+    // Allowed tool rotation range (deg)
+    pub r_min: f64,
+    pub r_max: f64,
+    // Largest translation allowed in a single relative move (mm)
+    pub max_step: f64,
+    // Largest rotation allowed in a single relative move (deg)
+    pub max_rotation_step: f64,
+    pub forbidden_zones: Vec<ForbiddenZone>,
+    // Rejects targets the arm can't reach when set. Rejects even when clamping, so the geometry
+    // has to match the arm or valid targets are refused.
+    pub kinematics: Option<ArmKinematics>,
+    pub action: ViolationAction,
+}
+
+impl SafetyConfig {
+    pub fn new() -> SafetyConfig {
+        SafetyConfig {
+            min: Vector3::new(150.0, -300.0, -150.0),
+            max: Vector3::new(400.0, 300.0, 150.0),
+            z_floor: -145.0,
+            r_min: -180.0,
+            r_max: 180.0,
+            max_step: 50.0,
+            max_rotation_step: 45.0,
+            forbidden_zones: Vec::new(),
+            kinematics: None,
+            action: ViolationAction::Clamp,
+        }
+    }
+
+    // Swapped bounds would make clamping panic
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (i, axis) in ['x', 'y', 'z'].into_iter().enumerate() {
+            if !is_range(self.min[i], self.max[i]) {
+                bail!("Safety bounds for {axis} are invalid, min {} is not below max {}", self.min[i], self.max[i]);
+            }
+        }
+        if !is_range(self.r_min, self.r_max) {
+            bail!("Safety rotation range is invalid, min {} is not below max {}", self.r_min, self.r_max);
+        }
+        if !self.z_floor.is_finite() || !is_range(0.0, self.max_step) || !is_range(0.0, self.max_rotation_step) {
+            bail!("Safety floor and step limits have to be finite and not negative");
+        }
+        if let Some(zone) = self.forbidden_zones.iter().find(|z| (0..3).any(|i| !is_range(z.min[i], z.max[i]))) {
+            bail!("Forbidden zone {} has min above max", zone.name);
+        }
+        Ok(())
+    }
+}
+
+impl Default for SafetyConfig {
+    fn default() -> SafetyConfig {
+        SafetyConfig::new()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SafetyViolation {
+    OutOfBounds { axis: char, value: f64, min: f64, max: f64 },
+    BelowFloor { z: f64, floor: f64 },
+    RotationOutOfRange { r: f64, min: f64, max: f64 },
+    StepTooLarge { distance: f64, max: f64 },
+    RotationStepTooLarge { rotation: f64, max: f64 },
+    InForbiddenZone { zone: String },
+    Unreachable { reason: String },
+    NotFinite,
+}
+
+impl fmt::Display for SafetyViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SafetyViolation::OutOfBounds { axis, value, min, max } => write!(f, "{axis} = {value} is outside of [{min}, {max}]"),
+            SafetyViolation::BelowFloor { z, floor } => write!(f, "z = {z} is below the floor at {floor}"),
+            SafetyViolation::RotationOutOfRange { r, min, max } => write!(f, "r = {r} is outside of [{min}, {max}]"),
+            SafetyViolation::StepTooLarge { distance, max } => write!(f, "Step of {distance} mm exceeds the maximum of {max} mm"),
+            SafetyViolation::RotationStepTooLarge { rotation, max } => write!(f, "Rotation step of {rotation} deg exceeds the maximum of {max} deg"),
+            SafetyViolation::InForbiddenZone { zone } => write!(f, "Target is inside forbidden zone {zone}"),
+            SafetyViolation::Unreachable { reason } => write!(f, "Target is unreachable: {reason}"),
+            SafetyViolation::NotFinite => write!(f, "Target contains a non finite value"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum SafetyCheck {
+    Allowed(Vector4<f64>),
+    // Target was changed to stay inside the envelope, the violations are the reasons for the change
+    Clamped(Vector4<f64>, Vec<SafetyViolation>),
+    Rejected(Vec<SafetyViolation>),
+}
+
+impl SafetyCheck {
+    pub fn target(&self) -> Option<Vector4<f64>> {
+        match self {
+            SafetyCheck::Allowed(target) | SafetyCheck::Clamped(target, _) => Some(*target),
+            SafetyCheck::Rejected(_) => None,
+        }
+    }
+
+    pub fn violations(&self) -> &[SafetyViolation] {
+        match self {
+            SafetyCheck::Allowed(_) => &[],
+            SafetyCheck::Clamped(_, violations) | SafetyCheck::Rejected(violations) => violations,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SafetyEnvelope {
+    config: SafetyConfig,
+}
+
+impl SafetyEnvelope {
+    pub fn new(config: SafetyConfig) -> anyhow::Result<SafetyEnvelope> {
+        config.validate()?;
+        Ok(SafetyEnvelope { config })
+    }
+
+    pub fn config(&self) -> &SafetyConfig {
+        &self.config
+    }
+
+    // Target is [x, y, z, r] in the same frame as MovJ
+    pub fn check_absolute(&self, target: &Vector4<f64>) -> SafetyCheck {
+        if target.iter().any(|v| !v.is_finite()) {
+            return SafetyCheck::Rejected(vec![SafetyViolation::NotFinite]);
+        }
+
+        let mut target = *target;
+        target.w = normalize_rotation(target.w);
+        let mut violations = Vec::new();
+        let clamped = self.clamp_to_volume(&target, &mut violations);
+        self.check_zones(&clamped, &mut violations);
+        self.check_reachable(&clamped, &mut violations);
+        self.finish(target, clamped, violations)
+    }
+
+    // Checks a move of `delta` from `current`, the step limits are applied before the absolute limits
+    pub fn check_relative(&self, current: &Vector4<f64>, delta: &Vector4<f64>) -> SafetyCheck {
+        if current.iter().chain(delta.iter()).any(|v| !v.is_finite()) {
+            return SafetyCheck::Rejected(vec![SafetyViolation::NotFinite]);
+        }
+
+        let mut violations = Vec::new();
+        let mut limited = *delta;
+
+        let translation = delta.xyz();
+        let distance = translation.norm();
+        if distance > self.config.max_step {
+            violations.push(SafetyViolation::StepTooLarge { distance, max: self.config.max_step });
+            let scaled = translation * (self.config.max_step / distance);
+            limited = Vector4::new(scaled.x, scaled.y, scaled.z, limited.w);
+        }
+        if delta.w.abs() > self.config.max_rotation_step {
+            violations.push(SafetyViolation::RotationStepTooLarge { rotation: delta.w, max: self.config.max_rotation_step });
+            limited.w = self.config.max_rotation_step.copysign(delta.w);
+        }
+
+        let mut target = current + delta;
+        target.w = normalize_rotation(target.w);
+        let mut limited = current + limited;
+        limited.w = normalize_rotation(limited.w);
+        let clamped = self.clamp_to_volume(&limited, &mut violations);
+        self.check_zones(&clamped, &mut violations);
+        self.check_reachable(&clamped, &mut violations);
+        self.finish(target, clamped, violations)
+    }
+
+    fn finish(&self, target: Vector4<f64>, clamped: Vector4<f64>, violations: Vec<SafetyViolation>) -> SafetyCheck {
+        if violations.is_empty() {
+            SafetyCheck::Allowed(target)
+        } else if self.config.action == ViolationAction::Reject
+            || violations.iter().any(|v| matches!(v, SafetyViolation::InForbiddenZone { .. } | SafetyViolation::Unreachable { .. })) {
+            // Forbidden zones can't always be clamped out of without crossing them, so they always reject
+            SafetyCheck::Rejected(violations)
+        } else {
+            SafetyCheck::Clamped(clamped, violations)
+        }
+    }
+
+    fn clamp_to_volume(&self, target: &Vector4<f64>, violations: &mut Vec<SafetyViolation>) -> Vector4<f64> {
+        let c = &self.config;
+        let mut res = *target;
+
+        for (i, axis) in ['x', 'y', 'z'].into_iter().enumerate() {
+            if res[i] < c.min[i] || res[i] > c.max[i] {
+                violations.push(SafetyViolation::OutOfBounds { axis, value: res[i], min: c.min[i], max: c.max[i] });
+                res[i] = res[i].clamp(c.min[i], c.max[i]);
+            }
+        }
+        if res.z < c.z_floor {
+            violations.push(SafetyViolation::BelowFloor { z: res.z, floor: c.z_floor });
+            res.z = c.z_floor;
+        }
+        if res.w < c.r_min || res.w > c.r_max {
+            violations.push(SafetyViolation::RotationOutOfRange { r: res.w, min: c.r_min, max: c.r_max });
+            res.w = res.w.clamp(c.r_min, c.r_max);
+        }
+
+        res
+    }
+
+    fn check_zones(&self, target: &Vector4<f64>, violations: &mut Vec<SafetyViolation>) {
+        let p = target.xyz();
+        for zone in self.config.forbidden_zones.iter().filter(|z| z.contains(&p)) {
+            violations.push(SafetyViolation::InForbiddenZone { zone: zone.name.clone() });
+        }
+    }
+
+    fn check_reachable(&self, target: &Vector4<f64>, violations: &mut Vec<SafetyViolation>) {
+        if let Some(Err(e)) = self.config.kinematics.as_ref().map(|k| k.inverse(target)) {
+            violations.push(SafetyViolation::Unreachable { reason: e.to_string() });
+        }
+    }
+}
+
+// False for swapped bounds and NaN
+fn is_range(min: f64, max: f64) -> bool {
+    min <= max
+}
+
+// Rotation wrapped to (-180, 180], so a turn past 180 continues from -180 instead of being clamped
+fn normalize_rotation(r: f64) -> f64 {
+    let r = r.rem_euclid(360.0);
+    if r > 180.0 { r - 360.0 } else { r }
+}
@@ -0,0 +1,287 @@
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use anyhow::bail;
+use nalgebra::Vector4;
+
+use crate::{dashboard::ErrorIds, feedback_data::FeedbackData, motion_queue::MotionParams, feedback_hub::{FeedbackHub, DEFAULT_HISTORY_CAPACITY}, kinematics::ArmKinematics, safety::{SafetyCheck, SafetyEnvelope}, EmergencyHandle, Robot};
+
+// Controller state, shared with the emergency handles so watchdog and collision trips act on the simulated arm
+struct SimState {
+    feedback: FeedbackData,
+    time_stamp: u64,
+    hub: FeedbackHub,
+}
+
+impl SimState {
+    fn publish(&mut self) {
+        // Dobot feedback is sent every 8 ms
+        self.time_stamp += 8;
+        self.feedback.time_stamp = self.time_stamp;
+        self.hub.publish(self.feedback.clone());
+    }
+
+    fn stop(&mut self) {
+        // Moves finish instantly, so stopping only clears the queue and holds the current pose
+        self.feedback.tool_vector_target = self.feedback.tool_vector_actual;
+        self.feedback.q_target = self.feedback.q_actual;
+        self.feedback.run_queued_cmd = 0;
+        self.feedback.pause_cmd_flag = 0;
+        self.feedback.running_status = 0;
+        self.publish();
+    }
+
+    fn disable(&mut self) {
+        self.feedback.enable_status = 0;
+        self.feedback.robot_mode = 4;
+        self.publish();
+    }
+
+    fn set_digital_output(&mut self, index: u32, status: bool) -> anyhow::Result<()> {
+        if !(1..=64).contains(&index) {
+            bail!("Digital output index {index} out of range");
+        }
+        let bit = 1 << (index - 1);
+        if status {
+            self.feedback.digital_outputs |= bit;
+        } else {
+            self.feedback.digital_outputs &= !bit;
+        }
+        self.publish();
+        Ok(())
+    }
+}
+
+// In-memory robot that reaches every target instantly, used by the simulator and for running without the arm
+pub struct SimulatedRobot {
+    state: Arc<Mutex<SimState>>,
+    safety: Option<SafetyEnvelope>,
+    hub: FeedbackHub,
+    kinematics: ArmKinematics,
+    error_ids: ErrorIds,
+}
+
+impl SimulatedRobot {
+    pub fn new(initial_pose: Vector4<f64>) -> SimulatedRobot {
+        let hub = FeedbackHub::new(DEFAULT_HISTORY_CAPACITY);
+        let robot = SimulatedRobot {
+            state: Arc::new(Mutex::new(SimState {
+                feedback: FeedbackData::blank(),
+                time_stamp: 0,
+                hub: hub.clone(),
+            })),
+            safety: None,
+            hub,
+            kinematics: ArmKinematics::mg400(),
+            error_ids: ErrorIds::default(),
+        };
+        let mut state = robot.state();
+        robot.set_pose(&mut state, initial_pose);
+        state.feedback.robot_mode = 4;
+        state.publish();
+        drop(state);
+        robot
+    }
+
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().unwrap()
+    }
+
+    pub fn set_safety_envelope(&mut self, envelope: Option<SafetyEnvelope>) {
+        self.safety = envelope;
+    }
+
+    // Puts the simulated controller into error mode, like a real alarm would
+    pub fn raise_alarm(&mut self, error_ids: ErrorIds) {
+        self.error_ids = error_ids;
+        let mut state = self.state();
+        state.feedback.error_status = 1;
+        state.feedback.robot_mode = 9;
+        state.publish();
+    }
+
+    // Moves the arm like a hand guiding it in drag mode, bypassing the safety envelope
+    pub fn drag_to(&mut self, pose: Vector4<f64>) -> anyhow::Result<()> {
+        let mut state = self.state();
+        if !state.feedback.is_dragging() {
+            bail!("Simulated robot is not in drag mode");
+        }
+        self.set_pose(&mut state, pose);
+        state.publish();
+
+        Ok(())
+    }
+
+    // Simulates pressing the record button on the control panel
+    pub fn set_record_button(&mut self, pressed: bool) {
+        let mut state = self.state();
+        state.feedback.record_button_signal = pressed as u8;
+        state.publish();
+    }
+
+    fn set_pose(&self, state: &mut SimState, pose: Vector4<f64>) {
+        for i in 0..4 {
+            state.feedback.tool_vector_actual[i] = pose[i];
+            state.feedback.tool_vector_target[i] = pose[i];
+        }
+        // Keep the joint angles consistent with the pose where possible
+        if let Ok(joints) = self.kinematics.inverse(&pose) {
+            state.feedback.q_actual[..4].copy_from_slice(&joints);
+            state.feedback.q_target[..4].copy_from_slice(&joints);
+        }
+    }
+
+    fn apply(&mut self, check: SafetyCheck) -> anyhow::Result<()> {
+        let mut state = self.state();
+        // Like the controller, a disabled arm (e.g. after a watchdog trip) doesn't move
+        if !state.feedback.is_enabled() {
+            bail!("Simulated robot is disabled");
+        }
+        let target = match check {
+            SafetyCheck::Allowed(target) => target,
+            SafetyCheck::Clamped(target, violations) => {
+                for v in &violations {
+                    log::warn!("Clamping simulated move: {v}");
+                }
+                target
+            }
+            SafetyCheck::Rejected(violations) => {
+                let reasons = violations.iter().map(|v| v.to_string()).collect::<Vec<_>>().join(", ");
+                bail!("Simulated move rejected by safety envelope: {reasons}");
+            }
+        };
+        self.set_pose(&mut state, target);
+        state.publish();
+
+        Ok(())
+    }
+}
+
+impl Robot for SimulatedRobot {
+    fn enable(&mut self) -> anyhow::Result<()> {
+        let mut state = self.state();
+        state.feedback.enable_status = 1;
+        state.feedback.robot_mode = 5;
+        state.publish();
+        Ok(())
+    }
+
+    fn disable(&mut self) -> anyhow::Result<()> {
+        self.state().disable();
+        Ok(())
+    }
+
+    fn move_to_with(&mut self, target: &Vector4<f64>, _params: &MotionParams) -> anyhow::Result<()> {
+        let check = match &self.safety {
+            Some(safety) => safety.check_absolute(target),
+            None => SafetyCheck::Allowed(*target),
+        };
+        self.apply(check)
+    }
+
+    fn move_relative(&mut self, delta: &Vector4<f64>) -> anyhow::Result<()> {
+        let current = self.pose();
+        let check = match &self.safety {
+            Some(safety) => safety.check_relative(&current, delta),
+            None => SafetyCheck::Allowed(current + delta),
+        };
+        self.apply(check)
+    }
+
+    fn stop(&mut self) -> anyhow::Result<()> {
+        self.state().stop();
+        Ok(())
+    }
+
+    // Moves finish instantly, so there is never any motion to pause
+
+    fn pause(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn resume(&mut self) -> anyhow::Result<()> {
+        Ok(())
+    }
+
+    fn start_drag(&mut self) -> anyhow::Result<()> {
+        let mut state = self.state();
+        if !state.feedback.is_enabled() {
+            bail!("Simulated robot has to be enabled for drag mode");
+        }
+        state.feedback.drag_status = 1;
+        state.feedback.robot_mode = 6;
+        state.publish();
+        Ok(())
+    }
+
+    fn stop_drag(&mut self) -> anyhow::Result<()> {
+        let mut state = self.state();
+        state.feedback.drag_status = 0;
+        state.feedback.robot_mode = if state.feedback.is_enabled() { 5 } else { 4 };
+        state.publish();
+        Ok(())
+    }
+
+    fn emergency_handle(&self) -> anyhow::Result<Box<dyn EmergencyHandle>> {
+        Ok(Box::new(SimulatedEmergencyHandle { state: self.state.clone() }))
+    }
+
+    fn set_digital_output(&mut self, index: u32, status: bool) -> anyhow::Result<()> {
+        self.state().set_digital_output(index, status)
+    }
+
+    fn pose(&self) -> Vector4<f64> {
+        let [x, y, z, r, ..] = self.state().feedback.tool_vector_actual;
+        Vector4::new(x, y, z, r)
+    }
+
+    fn feedback(&self) -> FeedbackData {
+        self.state().feedback.clone()
+    }
+
+    fn feedback_hub(&self) -> FeedbackHub {
+        self.hub.clone()
+    }
+
+    fn error_ids(&mut self) -> anyhow::Result<ErrorIds> {
+        Ok(self.error_ids.clone())
+    }
+
+    fn clear_error(&mut self) -> anyhow::Result<()> {
+        self.error_ids = ErrorIds::default();
+        let mut state = self.state();
+        state.feedback.error_status = 0;
+        state.feedback.robot_mode = if state.feedback.enable_status != 0 { 5 } else { 4 };
+        state.publish();
+        Ok(())
+    }
+}
+
+pub struct SimulatedEmergencyHandle {
+    state: Arc<Mutex<SimState>>,
+}
+
+impl SimulatedEmergencyHandle {
+    // Still usable after a panic while the robot held the lock, like the dashboard socket would be
+    fn state(&self) -> MutexGuard<'_, SimState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
+
+impl EmergencyHandle for SimulatedEmergencyHandle {
+    fn stop(&mut self) -> anyhow::Result<()> {
+        log::warn!("Stop requested for simulated robot");
+        self.state().stop();
+        Ok(())
+    }
+
+    fn disable(&mut self) -> anyhow::Result<()> {
+        log::warn!("Disable requested for simulated robot");
+        self.state().disable();
+        Ok(())
+    }
+
+    fn set_digital_output(&mut self, index: u32, status: bool) -> anyhow::Result<()> {
+        log::warn!("Simulated robot output {index} set to {status} from emergency handle");
+        self.state().set_digital_output(index, status)
+    }
+}
@@ -0,0 +1,225 @@
+use std::{fs, path::{Path, PathBuf}, sync::mpsc::Receiver};
+
+use anyhow::{anyhow, bail};
+use nalgebra::Vector4;
+
+use crate::{feedback_data::FeedbackData, motion_queue::{MotionParams, MotionQueue, MotionQueueConfig}, Robot};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrajectoryPoint {
+    // Seconds since the start of the recording
+    pub time: f64,
+    pub pose: Vector4<f64>,
+    // Marked with the record button on the control panel
+    pub keyframe: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PlaybackMode {
+    // Only move through the points marked with the record button, stopping at each
+    Keyframes,
+    // Follow the recorded path, blending through every point
+    Path { blend: u8 },
+}
+
+#[derive(Debug, Clone)]
+pub struct Trajectory {
+    pub name: String,
+    pub points: Vec<TrajectoryPoint>,
+}
+
+impl Trajectory {
+    pub fn new(name: &str) -> Trajectory {
+        Trajectory { name: name.to_string(), points: Vec::new() }
+    }
+
+    pub fn duration(&self) -> f64 {
+        self.points.last().map(|p| p.time).unwrap_or(0.0)
+    }
+
+    pub fn keyframes(&self) -> impl Iterator<Item = &TrajectoryPoint> {
+        self.points.iter().filter(|p| p.keyframe)
+    }
+
+    // Saves to `<dir>/<name>.csv`
+    pub fn save(&self, dir: &Path) -> anyhow::Result<PathBuf> {
+        if self.name.is_empty() || self.name.contains(['/', '\\']) {
+            bail!("Invalid trajectory name {:?}", self.name);
+        }
+        fs::create_dir_all(dir)?;
+        let path = dir.join(format!("{}.csv", self.name));
+        let mut writer = csv::Writer::from_path(&path)?;
+        writer.write_record(["time", "x", "y", "z", "r", "keyframe"])?;
+        for p in &self.points {
+            writer.write_record([
+                p.time.to_string(),
+                p.pose.x.to_string(),
+                p.pose.y.to_string(),
+                p.pose.z.to_string(),
+                p.pose.w.to_string(),
+                (p.keyframe as u8).to_string(),
+            ])?;
+        }
+        writer.flush()?;
+
+        Ok(path)
+    }
+
+    // The trajectory is named after the file
+    pub fn load(path: &Path) -> anyhow::Result<Trajectory> {
+        let name = path
+            .file_stem()
+            .and_then(|s| s.to_str())
+            .ok_or(anyhow!("Invalid trajectory path {}", path.display()))?;
+        let mut trajectory = Trajectory::new(name);
+        let mut reader = csv::Reader::from_path(path)?;
+        for (i, record) in reader.records().enumerate() {
+            let record = record?;
+            if record.len() != 6 {
+                bail!("Line {} of {} has {} columns, expected 6", i + 2, path.display(), record.len());
+            }
+            let value = |c: usize| record[c].trim().parse::<f64>();
+            trajectory.points.push(TrajectoryPoint {
+                time: value(0)?,
+                pose: Vector4::new(value(1)?, value(2)?, value(3)?, value(4)?),
+                keyframe: record[5].trim() == "1",
+            });
+        }
+
+        Ok(trajectory)
+    }
+
+    // Builds a queue that replays the trajectory, starting with a regular move to the first point
+    pub fn to_queue(&self, mode: PlaybackMode, config: MotionQueueConfig) -> MotionQueue {
+        let mut queue = MotionQueue::new(config);
+        match mode {
+            PlaybackMode::Keyframes => {
+                for p in self.keyframes() {
+                    queue.push_move(p.pose, MotionParams::default());
+                }
+            }
+            PlaybackMode::Path { blend } => {
+                let last = self.points.len().saturating_sub(1);
+                for (i, p) in self.points.iter().enumerate() {
+                    // Stop exactly at the first and last point
+                    let blend = if i == 0 || i == last { None } else { Some(blend) };
+                    queue.push_move(p.pose, MotionParams { blend, ..MotionParams::default() });
+                }
+            }
+        }
+        queue
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TeachConfig {
+    // Poses closer than this to the last recorded point are skipped (mm), feedback arrives every 8 ms
+    pub min_distance: f64,
+    // Smallest rotation change that is recorded even if the position didn't change (deg)
+    pub min_rotation: f64,
+}
+
+impl TeachConfig {
+    pub fn new() -> TeachConfig {
+        TeachConfig {
+            min_distance: 2.0,
+            min_rotation: 1.0,
+        }
+    }
+}
+
+impl Default for TeachConfig {
+    fn default() -> TeachConfig {
+        TeachConfig::new()
+    }
+}
+
+// Builds a trajectory from feedback frames, keeping a point whenever the arm moved far enough
+pub struct TeachRecorder {
+    config: TeachConfig,
+    trajectory: Trajectory,
+    start_time: Option<u64>,
+    record_button: bool,
+}
+
+impl TeachRecorder {
+    pub fn new(name: &str, config: TeachConfig) -> TeachRecorder {
+        TeachRecorder {
+            config,
+            trajectory: Trajectory::new(name),
+            start_time: None,
+            record_button: false,
+        }
+    }
+
+    pub fn record(&mut self, feedback: &FeedbackData) {
+        let [x, y, z, r, ..] = feedback.tool_vector_actual;
+        let pose = Vector4::new(x, y, z, r);
+        let start = *self.start_time.get_or_insert(feedback.time_stamp);
+        let time = feedback.time_stamp.saturating_sub(start) as f64 / 1000.0;
+
+        let pressed = feedback.is_record_button_pressed();
+        let keyframe = pressed && !self.record_button;
+        self.record_button = pressed;
+
+        let moved = match self.trajectory.points.last() {
+            Some(last) => {
+                (pose - last.pose).xyz().norm() >= self.config.min_distance
+                    || (pose.w - last.pose.w).abs() >= self.config.min_rotation
+            }
+            None => true,
+        };
+        if !moved {
+            // A keyframe at the same spot as the last point marks that point instead of adding a new one
+            if let (true, Some(last)) = (keyframe, self.trajectory.points.last_mut()) {
+                last.keyframe = true;
+            }
+            return;
+        }
+        self.trajectory.points.push(TrajectoryPoint { time, pose, keyframe });
+    }
+
+    pub fn trajectory(&self) -> &Trajectory {
+        &self.trajectory
+    }
+
+    pub fn finish(self) -> Trajectory {
+        self.trajectory
+    }
+}
+
+// Puts the robot into drag mode and records the hand-guided motion until `finish` is called
+pub struct TeachSession {
+    recorder: TeachRecorder,
+    feedback: Receiver<FeedbackData>,
+}
+
+impl TeachSession {
+    pub fn start(robot: &mut dyn Robot, name: &str, config: TeachConfig) -> anyhow::Result<TeachSession> {
+        let feedback = robot.subscribe_feedback();
+        robot.start_drag()?;
+
+        Ok(TeachSession {
+            recorder: TeachRecorder::new(name, config),
+            feedback,
+        })
+    }
+
+    // Records every frame received since the last call, should be called regularly
+    pub fn poll(&mut self) -> &Trajectory {
+        for frame in self.feedback.try_iter() {
+            // Frames from before drag mode was active are not part of the demonstration
+            if frame.is_dragging() {
+                self.recorder.record(&frame);
+            }
+        }
+        self.recorder.trajectory()
+    }
+
+    pub fn finish(mut self, robot: &mut dyn Robot) -> anyhow::Result<Trajectory> {
+        self.poll();
+        robot.stop_drag()?;
+
+        Ok(self.recorder.finish())
+    }
+}
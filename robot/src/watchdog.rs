@@ -0,0 +1,182 @@
+use std::{panic, sync::{Arc, Mutex, MutexGuard, TryLockError}, thread::{self, sleep}, time::{Duration, Instant}};
+
+use crate::EmergencyHandle;
+
+// Tries to get the watchdog lock for about 100 ms in the panic hook
+const PANIC_LOCK_ATTEMPTS: u32 = 100;
+
+#[derive(Debug, Clone)]
+pub struct WatchdogConfig {
+    // Time without a heartbeat after which the robot is stopped
+    pub timeout: Duration,
+    // Also disable the robot, so it can't move again until it is enabled
+    pub disable_robot: bool,
+    // Outputs driven when the watchdog trips, e.g. to switch off the vacuum
+    pub safe_outputs: Vec<(u32, bool)>,
+}
+
+impl WatchdogConfig {
+    pub fn new() -> WatchdogConfig {
+        WatchdogConfig {
+            timeout: Duration::from_secs(15),
+            disable_robot: false,
+            safe_outputs: Vec::new(),
+        }
+    }
+}
+
+impl Default for WatchdogConfig {
+    fn default() -> WatchdogConfig {
+        WatchdogConfig::new()
+    }
+}
+
+struct WatchdogState {
+    config: WatchdogConfig,
+    handle: Box<dyn EmergencyHandle>,
+    armed: bool,
+    last_heartbeat: Instant,
+    tripped: Option<String>,
+    // Set by a heartbeat timeout, the next heartbeat shows the loop is back and arms again
+    rearm_on_heartbeat: bool,
+    running: bool,
+}
+
+impl WatchdogState {
+    fn trip(&mut self, reason: &str) {
+        self.armed = false;
+        self.rearm_on_heartbeat = false;
+        self.tripped = Some(reason.to_string());
+        log::error!("Watchdog tripped: {reason}, stopping robot");
+
+        // Keep going on errors, every step that succeeds makes the robot safer
+        if let Err(e) = self.handle.stop() {
+            log::error!("Watchdog failed to stop robot: {e}");
+        }
+        if self.config.disable_robot {
+            if let Err(e) = self.handle.disable() {
+                log::error!("Watchdog failed to disable robot: {e}");
+            }
+        }
+        for (index, status) in self.config.safe_outputs.clone() {
+            if let Err(e) = self.handle.set_digital_output(index, status) {
+                log::error!("Watchdog failed to set output {index}: {e}");
+            }
+        }
+    }
+}
+
+// Stops the robot when the control loop stops sending heartbeats, e.g. because the AERA link dropped.
+// Starts disarmed, call `arm` once the loop is running.
+#[derive(Clone)]
+pub struct Watchdog {
+    state: Arc<Mutex<WatchdogState>>,
+}
+
+impl Watchdog {
+    pub fn spawn(config: WatchdogConfig, handle: Box<dyn EmergencyHandle>) -> Watchdog {
+        let poll_interval = (config.timeout / 10).max(Duration::from_millis(10));
+        let state = Arc::new(Mutex::new(WatchdogState {
+            config,
+            handle,
+            armed: false,
+            last_heartbeat: Instant::now(),
+            tripped: None,
+            rearm_on_heartbeat: false,
+            running: true,
+        }));
+
+        let thread_state = state.clone();
+        thread::spawn(move || loop {
+            sleep(poll_interval);
+            let mut state = lock(&thread_state);
+            if !state.running {
+                return;
+            }
+            if state.armed && state.last_heartbeat.elapsed() > state.config.timeout {
+                let reason = format!("No heartbeat for {:?}", state.config.timeout);
+                state.trip(&reason);
+                state.rearm_on_heartbeat = true;
+            }
+        });
+
+        Watchdog { state }
+    }
+
+    // Re-arms after a heartbeat timeout, trips for other reasons need an explicit `arm`
+    pub fn heartbeat(&self) {
+        let mut state = lock(&self.state);
+        state.last_heartbeat = Instant::now();
+        if state.rearm_on_heartbeat {
+            log::warn!("Watchdog re-armed, heartbeats resumed after: {}", state.tripped.as_deref().unwrap_or("timeout"));
+            state.armed = true;
+            state.rearm_on_heartbeat = false;
+            state.tripped = None;
+        }
+    }
+
+    // Starts watching for heartbeats, also after the watchdog has tripped
+    pub fn arm(&self) {
+        let mut state = lock(&self.state);
+        state.armed = true;
+        state.tripped = None;
+        state.rearm_on_heartbeat = false;
+        state.last_heartbeat = Instant::now();
+    }
+
+    pub fn disarm(&self) {
+        let mut state = lock(&self.state);
+        state.armed = false;
+        state.rearm_on_heartbeat = false;
+    }
+
+    // Puts the robot into the safe state right away, e.g. when the control loop failed
+    pub fn trip(&self, reason: &str) {
+        lock(&self.state).trip(reason);
+    }
+
+    // Reason for the last trip, cleared when the watchdog is armed again
+    pub fn tripped(&self) -> Option<String> {
+        lock(&self.state).tripped.clone()
+    }
+
+    // Trips the watchdog when any thread panics, then runs the previously installed hook
+    pub fn install_panic_hook(&self) {
+        let state = self.state.clone();
+        let previous = panic::take_hook();
+        panic::set_hook(Box::new(move |info| {
+            // Don't block forever if the panicking thread already holds the lock
+            let reason = format!("Panic: {info}");
+            let mut tripped = false;
+            for _ in 0..PANIC_LOCK_ATTEMPTS {
+                match state.try_lock() {
+                    Ok(mut state) => state.trip(&reason),
+                    Err(TryLockError::Poisoned(e)) => e.into_inner().trip(&reason),
+                    Err(TryLockError::WouldBlock) => {
+                        sleep(Duration::from_millis(1));
+                        continue;
+                    }
+                }
+                tripped = true;
+                break;
+            }
+            if !tripped {
+                log::error!("Watchdog is busy, could not stop robot after panic");
+            }
+            previous(info);
+        }));
+    }
+
+    // Stops the watchdog thread without tripping
+    pub fn shutdown(&self) {
+        let mut state = lock(&self.state);
+        state.armed = false;
+        state.rearm_on_heartbeat = false;
+        state.running = false;
+    }
+}
+
+// The state stays usable after a panic while it was locked, stopping the robot matters more than consistency
+fn lock(state: &Mutex<WatchdogState>) -> MutexGuard<'_, WatchdogState> {
+    state.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -5,10 +5,11 @@ mod utils;
 use anyhow::Ok;
 use nalgebra::{DMatrix, Vector2};
 use opencv::{core::{Mat, Point, Rect, Scalar}, highgui::{self, imshow, wait_key}, imgcodecs::{self, IMREAD_COLOR}, imgproc::{arrowed_line, rectangle_def}};
+use pixy2::frame::Frame;
 use preprocess::preprocess_image;
-use vision::{classifier::{utils::get_image_data_for_classification, Classifier}, proposals::{color_gradient, motion::{self, motion_matrix::calc_motion_matrix}, proposal_area::ProposalArea}};
+use vision::{classifier::{utils::get_image_data_for_classification, Classifier}, proposals::{color_gradient, motion::{self, motion_matrix::calc_motion_matrix}}};
 
-pub use vision::proposals::proposal_area::RecognizedArea;
+pub use vision::proposals::proposal_area::{ProposalArea, RecognizedArea};
 
 pub struct VisionSystem {
     classifier: Classifier,
@@ -21,8 +22,19 @@ impl VisionSystem {
         }
     }
 
-    pub fn process_frame(&mut self, img: &Mat) -> anyhow::Result<Vec<RecognizedArea>> {
+    // Converted to BGR first, the color proposals segment on HSV converted from BGR
+    pub fn process_frame(&mut self, frame: &Frame) -> anyhow::Result<Vec<RecognizedArea>> {
+        let img = frame.to_bgr()?;
         let proposals = color_gradient::make_proposals(&img)?;
+        self.classify_bgr(&img, proposals)
+    }
+
+    // Classifies areas found by another proposal source, e.g. the blocks detected on the Pixy2
+    pub fn classify_proposals(&mut self, frame: &Frame, proposals: Vec<ProposalArea>) -> anyhow::Result<Vec<RecognizedArea>> {
+        self.classify_bgr(&frame.to_bgr()?, proposals)
+    }
+
+    fn classify_bgr(&mut self, img: &Mat, proposals: Vec<ProposalArea>) -> anyhow::Result<Vec<RecognizedArea>> {
         let (img_gray, _img_small) = preprocess_image(&img)?;
 
         let mut results = Vec::new();